@@ -0,0 +1,209 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Error, anyhow};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+
+const MAX_LABEL_LENGTH: usize = 63;
+const MAX_NAME_LENGTH: usize = 253;
+
+/// A validated DNS name in canonical form.
+///
+/// Names are lower-cased, stored without a trailing dot and with
+/// internationalized labels converted to punycode. Every label must be 1 to 63
+/// octets of letters, digits, `-` or `_`, and the whole name at most 253
+/// octets. The leftmost label may also contain `*` for wildcards and registry
+/// records derived from them.
+///
+/// The empty name is valid, as Unbound uses an empty hostname for the zone
+/// apex.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DnsName(String);
+
+impl DnsName {
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        let name = name.trim().trim_end_matches('.');
+        if name.is_empty() {
+            return Ok(DnsName::default());
+        }
+
+        let ascii = idna::domain_to_ascii(name)
+            .map_err(|e| anyhow!("Invalid DNS name {:?}: {}", name, e))?;
+        if ascii.len() > MAX_NAME_LENGTH {
+            Err(anyhow!(
+                "DNS name {:?} is longer than {} octets",
+                name,
+                MAX_NAME_LENGTH
+            ))?
+        }
+
+        for (index, label) in ascii.split('.').enumerate() {
+            if label.is_empty() || label.len() > MAX_LABEL_LENGTH {
+                Err(anyhow!(
+                    "DNS name {:?} has a label that is empty or longer than {} octets",
+                    name,
+                    MAX_LABEL_LENGTH
+                ))?
+            }
+            let valid = label.chars().all(|c| {
+                c.is_ascii_alphanumeric() || c == '-' || c == '_' || (index == 0 && c == '*')
+            });
+            if !valid {
+                Err(anyhow!(
+                    "DNS name {:?} has an invalid label {:?}",
+                    name,
+                    label
+                ))?
+            }
+        }
+
+        Ok(DnsName(ascii))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The name with punycode labels decoded, for display.
+    pub fn to_unicode(&self) -> String {
+        idna::domain_to_unicode(&self.0).0
+    }
+
+    /// The fully qualified name of hostname `self` within `domain`. An empty
+    /// hostname is `domain` itself.
+    pub fn join(&self, domain: &DnsName) -> Result<DnsName, Error> {
+        match (self.is_empty(), domain.is_empty()) {
+            (true, _) => Ok(domain.clone()),
+            (false, true) => Ok(self.clone()),
+            (false, false) => DnsName::parse(&format!("{}.{}", self.0, domain.0)),
+        }
+    }
+
+    /// Whether `self` is `domain` or one of its subdomains.
+    pub fn is_within(&self, domain: &DnsName) -> bool {
+        self.relative_to(domain).is_some()
+    }
+
+    /// The hostname of `self` within `domain`, empty for `domain` itself.
+    pub fn relative_to(&self, domain: &DnsName) -> Option<DnsName> {
+        if domain.is_empty() {
+            return Some(self.clone());
+        }
+        if self == domain {
+            return Some(DnsName::default());
+        }
+
+        self.0
+            .strip_suffix(&domain.0)
+            .and_then(|hostname| hostname.strip_suffix('.'))
+            .map(|hostname| DnsName(hostname.to_string()))
+    }
+
+    /// Split off the leftmost label.
+    pub fn split_first(&self) -> Option<(&str, DnsName)> {
+        self.0
+            .split_once('.')
+            .map(|(first, rest)| (first, DnsName(rest.to_string())))
+    }
+}
+
+impl fmt::Display for DnsName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for DnsName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DnsName::parse(s)
+    }
+}
+
+impl PartialEq<str> for DnsName {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for DnsName {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl Serialize for DnsName {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for DnsName {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        DnsName::parse(&s).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_canonical_form() {
+        let name = DnsName::parse("API.Example.COM.").unwrap();
+        assert_eq!(name.as_str(), "api.example.com");
+        assert_eq!(DnsName::parse("").unwrap(), DnsName::default());
+    }
+
+    #[test]
+    fn test_punycode() {
+        let name = DnsName::parse("Bücher.example.com").unwrap();
+        assert_eq!(name.as_str(), "xn--bcher-kva.example.com");
+        assert_eq!(name.to_unicode(), "bücher.example.com");
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(DnsName::parse("*.apps.example.com").is_ok());
+        assert!(DnsName::parse("a-*.apps.example.com").is_ok());
+        assert!(DnsName::parse("apps.*.example.com").is_err());
+    }
+
+    #[test]
+    fn test_length_limits() {
+        let label = "a".repeat(63);
+        assert!(DnsName::parse(&format!("{}.example.com", label)).is_ok());
+        assert!(DnsName::parse(&format!("a{}.example.com", label)).is_err());
+
+        let name = [label.as_str(); 4].join(".");
+        assert!(DnsName::parse(&name).is_err());
+        assert!(DnsName::parse("api..example.com").is_err());
+        assert!(DnsName::parse("api example.com").is_err());
+    }
+
+    #[test]
+    fn test_relative_names() {
+        let domain = DnsName::parse("example.com").unwrap();
+        let name = DnsName::parse("db.prod.example.com").unwrap();
+        assert_eq!(name.relative_to(&domain).unwrap(), "db.prod");
+        assert_eq!(domain.relative_to(&domain).unwrap(), DnsName::default());
+        assert!(!DnsName::parse("badexample.com").unwrap().is_within(&domain));
+
+        let hostname = DnsName::parse("db.prod").unwrap();
+        assert_eq!(hostname.join(&domain).unwrap(), name);
+        assert_eq!(DnsName::default().join(&domain).unwrap(), domain);
+    }
+}
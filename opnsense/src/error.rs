@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Why an OPNsense API call failed.
+///
+/// Returned wrapped in an `anyhow::Error`, so callers that care can recover it
+/// with `downcast_ref`.
+#[derive(Debug)]
+pub enum ApiError {
+    /// OPNsense could not be reached, or replied with something unexpected.
+    Transport(reqwest::Error),
+    /// The API key or secret was rejected.
+    Unauthorized(reqwest::StatusCode),
+    /// The endpoint or row does not exist.
+    NotFound(String),
+    /// OPNsense refused the change, with its validation messages by field.
+    Validation(HashMap<String, String>),
+    /// The change cannot be made on this OPNsense release.
+    Unsupported(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Transport(e) => write!(f, "Failed to reach OPNsense: {}", e),
+            ApiError::Unauthorized(status) => {
+                write!(f, "OPNsense rejected the API credentials ({})", status)
+            }
+            ApiError::NotFound(what) => write!(f, "Not found in OPNsense: {}", what),
+            ApiError::Validation(validations) => {
+                write!(f, "Operation failed: {:?}", validations)
+            }
+            ApiError::Unsupported(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> Self {
+        match e.status() {
+            Some(status)
+                if status == reqwest::StatusCode::UNAUTHORIZED
+                    || status == reqwest::StatusCode::FORBIDDEN =>
+            {
+                ApiError::Unauthorized(status)
+            }
+            Some(reqwest::StatusCode::NOT_FOUND) => ApiError::NotFound(
+                e.url()
+                    .map(|url| url.path().to_string())
+                    .unwrap_or_default(),
+            ),
+            _ => ApiError::Transport(e),
+        }
+    }
+}
@@ -1,26 +1,37 @@
+pub mod dns_name;
+pub mod error;
 pub mod models;
 
 use std::collections::HashMap;
 
 use anyhow::{Context, Error, Result, anyhow};
 use base64::{Engine, engine::general_purpose};
-use reqwest;
 use reqwest::header;
+use serde::de::DeserializeOwned;
 
+pub use crate::error::ApiError;
+
+#[derive(Clone)]
 pub struct Opnsense {
     pub url: String,
 
     client: reqwest::Client,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum ApiEndpoint {
     UnboundServiceStatus,
+    UnboundServiceReconfigure,
+    UnboundServiceRestart,
     UnboundSearchHostOverrides,
     UnboundAddHostOverride,
+    UnboundSetHostOverride,
     UnboundDelHostOverride,
     UnboundSearchHostAliases,
+    UnboundGetHostAlias,
     UnboundAddHostAlias,
+    UnboundSetHostAlias,
     UnboundDelHostAlias,
 }
 
@@ -28,11 +39,16 @@ impl From<ApiEndpoint> for &'static str {
     fn from(endpoint: ApiEndpoint) -> Self {
         match endpoint {
             ApiEndpoint::UnboundServiceStatus => "/api/unbound/service/status",
+            ApiEndpoint::UnboundServiceReconfigure => "/api/unbound/service/reconfigure",
+            ApiEndpoint::UnboundServiceRestart => "/api/unbound/service/restart",
             ApiEndpoint::UnboundSearchHostOverrides => "/api/unbound/settings/searchHostOverride/",
             ApiEndpoint::UnboundAddHostOverride => "/api/unbound/settings/addHostOverride/",
+            ApiEndpoint::UnboundSetHostOverride => "/api/unbound/settings/setHostOverride/",
             ApiEndpoint::UnboundDelHostOverride => "/api/unbound/settings/delHostOverride/",
             ApiEndpoint::UnboundSearchHostAliases => "/api/unbound/settings/searchHostAlias/",
+            ApiEndpoint::UnboundGetHostAlias => "/api/unbound/settings/getHostAlias/",
             ApiEndpoint::UnboundAddHostAlias => "/api/unbound/settings/addHostAlias/",
+            ApiEndpoint::UnboundSetHostAlias => "/api/unbound/settings/setHostAlias/",
             ApiEndpoint::UnboundDelHostAlias => "/api/unbound/settings/delHostAlias/",
         }
     }
@@ -78,22 +94,119 @@ impl Opnsense {
         format!("{}/{}", self.url, clean)
     }
 
+    /// Send `request` and parse the JSON response, classifying failures.
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, ApiError> {
+        let resp = request.send().await?.error_for_status()?;
+        Ok(resp.json::<T>().await?)
+    }
+
     pub async fn unbound_get_status(&self) -> Result<models::Status, Error> {
         let endpoint: &str = ApiEndpoint::UnboundServiceStatus.into();
         let url = self.url(endpoint);
-        let resp = self.client.get(url).send().await?;
-        let parsed = resp.json::<models::Status>().await?;
+        let parsed: models::Status = self.send(self.client.get(url)).await?;
         Ok(parsed)
     }
 
-    pub async fn unbound_get_host_overrides(&self) -> Result<models::HostOverride, Error> {
-        let endpoint: &str = ApiEndpoint::UnboundSearchHostOverrides.into();
+    /// Regenerate Unbound's configuration from `config.xml` and reload it, so
+    /// changes made through the settings API are served.
+    pub async fn unbound_reconfigure(&self) -> Result<(), Error> {
+        self.unbound_service_action(ApiEndpoint::UnboundServiceReconfigure)
+            .await
+            .context("Failed to reconfigure Unbound")
+    }
+
+    /// Restart Unbound, which also applies pending changes but drops its cache.
+    pub async fn unbound_restart(&self) -> Result<(), Error> {
+        self.unbound_service_action(ApiEndpoint::UnboundServiceRestart)
+            .await
+            .context("Failed to restart Unbound")
+    }
+
+    async fn unbound_service_action(&self, endpoint: ApiEndpoint) -> Result<(), Error> {
+        let endpoint: &str = endpoint.into();
+        let url = self.url(endpoint);
+        let parsed: models::ServiceResult = self.send(self.client.post(url).body("{}")).await?;
+        if parsed.status.eq_ignore_ascii_case("failed")
+            || parsed.response.trim().eq_ignore_ascii_case("failed")
+        {
+            Err(anyhow!("OPNsense reported {:?}", parsed))?
+        }
+
+        Ok(())
+    }
+
+    /// Fetch one page of a `search*` endpoint.
+    async fn search<T: DeserializeOwned>(
+        &self,
+        endpoint: ApiEndpoint,
+        request: &models::SearchRequest,
+    ) -> Result<models::SearchResult<T>, Error> {
+        let endpoint: &str = endpoint.into();
         let url = self.url(endpoint);
-        let resp = self.client.get(url).send().await?;
-        let parsed = resp.json::<models::HostOverride>().await?;
+        let parsed = self.send(self.client.post(url).json(request)).await?;
         Ok(parsed)
     }
 
+    /// Fetch every page of a `search*` endpoint from `request.current` on.
+    async fn search_all<T: DeserializeOwned>(
+        &self,
+        endpoint: ApiEndpoint,
+        request: &models::SearchRequest,
+    ) -> Result<models::SearchResult<T>, Error> {
+        let mut request = request.clone();
+        let mut all = models::SearchResult {
+            rows: vec![],
+            row_count: 0,
+            total: 0,
+            current: request.current,
+        };
+        loop {
+            let page: models::SearchResult<T> = self.search(endpoint.clone(), &request).await?;
+            let fetched = page.rows.len() as u64;
+            all.rows.extend(page.rows);
+            all.total = page.total;
+            // A short page is the last one, whatever total claims, and a zero
+            // page size leaves the page size to OPNsense.
+            if fetched == 0
+                || fetched < request.row_count
+                || request.row_count == 0
+                || request.current * request.row_count >= page.total
+            {
+                break;
+            }
+            request.current += 1;
+        }
+
+        all.row_count = all.rows.len() as u64;
+        Ok(all)
+    }
+
+    /// Fetch one page of host overrides.
+    pub async fn unbound_search_host_overrides(
+        &self,
+        request: &models::SearchRequest,
+    ) -> Result<models::HostOverride, Error> {
+        self.search(ApiEndpoint::UnboundSearchHostOverrides, request)
+            .await
+    }
+
+    /// Fetch every host override matching `request`, across pages.
+    pub async fn unbound_search_all_host_overrides(
+        &self,
+        request: &models::SearchRequest,
+    ) -> Result<models::HostOverride, Error> {
+        self.search_all(ApiEndpoint::UnboundSearchHostOverrides, request)
+            .await
+    }
+
+    pub async fn unbound_get_host_overrides(&self) -> Result<models::HostOverride, Error> {
+        self.unbound_search_all_host_overrides(&models::SearchRequest::default())
+            .await
+    }
+
     pub async fn unbound_add_host_override(
         &self,
         new: &models::NewHostOverride,
@@ -102,41 +215,91 @@ impl Opnsense {
         let url = self.url(endpoint);
         let payload: HashMap<&str, &models::NewHostOverride> =
             [("host", new)].into_iter().collect();
-        let resp = self.client.post(url).json(&payload).send().await?;
-        let resp = resp.error_for_status()?;
-        let parsed = resp.json::<models::ApiResult>().await?;
-
-        if parsed.result == "failed" {
-            Err(anyhow!(format!(
-                "Operation failed: {:?}",
+        let parsed: models::ApiResult = self.send(self.client.post(url).json(&payload)).await?;
+
+        // Releases predating TXT host overrides reject the record type itself.
+        let rr_rejected = parsed
+            .validations
+            .as_ref()
+            .is_some_and(|v| v.contains_key("host.rr"));
+        if parsed.result == "failed" && new.rr == models::HostOverrideType::TXT && rr_rejected {
+            Err(ApiError::Unsupported(format!(
+                "This OPNsense release does not support TXT host overrides: {:?}",
                 parsed.validations
             )))?
         }
 
+        if parsed.result == "failed" {
+            Err(ApiError::Validation(parsed.validations.unwrap_or_default()))?
+        }
+
         parsed.uuid.ok_or(anyhow!("Failed to parse UUID"))
     }
 
+    pub async fn unbound_set_host_override(
+        &self,
+        uuid: String,
+        update: &models::HostOverrideUpdate,
+    ) -> Result<(), Error> {
+        let endpoint: &str = ApiEndpoint::UnboundSetHostOverride.into();
+        let url = self.url(endpoint) + &uuid;
+        let payload: HashMap<&str, &models::HostOverrideUpdate> =
+            [("host", update)].into_iter().collect();
+        let parsed: models::ApiResult = self.send(self.client.post(url).json(&payload)).await?;
+        if parsed.result != "saved" {
+            Err(ApiError::Validation(parsed.validations.unwrap_or_default()))?
+        }
+
+        Ok(())
+    }
+
     pub async fn unbound_del_host_override(&self, uuid: String) -> Result<(), Error> {
         let endpoint: &str = ApiEndpoint::UnboundDelHostOverride.into();
         let url = self.url(endpoint) + &uuid;
-        let resp = self.client.post(&url).body("{}").send().await?;
-        let parsed = resp.json::<models::ApiResult>().await?;
+        let parsed: models::ApiResult = self.send(self.client.post(&url).body("{}")).await?;
+        if parsed.result == "not found" {
+            Err(ApiError::NotFound(uuid))?
+        }
         if parsed.result != "deleted" {
-            Err(anyhow!(format!(
-                "Operation failed: {:?}",
-                parsed.validations
-            )))?
+            Err(ApiError::Validation(parsed.validations.unwrap_or_default()))?
         }
 
         Ok(())
     }
 
+    /// Fetch one page of host aliases.
+    pub async fn unbound_search_host_aliases(
+        &self,
+        request: &models::SearchRequest,
+    ) -> Result<models::HostAlias, Error> {
+        self.search(ApiEndpoint::UnboundSearchHostAliases, request)
+            .await
+    }
+
+    /// Fetch every host alias matching `request`, across pages.
+    pub async fn unbound_search_all_host_aliases(
+        &self,
+        request: &models::SearchRequest,
+    ) -> Result<models::HostAlias, Error> {
+        self.search_all(ApiEndpoint::UnboundSearchHostAliases, request)
+            .await
+    }
+
     pub async fn unbound_get_host_aliases(&self) -> Result<models::HostAlias, Error> {
-        let endpoint: &str = ApiEndpoint::UnboundSearchHostAliases.into();
-        let url = self.url(endpoint);
-        let resp = self.client.get(url).send().await?;
-        let parsed = resp.json::<models::HostAlias>().await?;
-        Ok(parsed)
+        self.unbound_search_all_host_aliases(&models::SearchRequest::default())
+            .await
+    }
+
+    /// Fetch a single host alias, which unlike the search identifies its
+    /// parent by UUID.
+    pub async fn unbound_get_host_alias(
+        &self,
+        uuid: String,
+    ) -> Result<models::HostAliasItem, Error> {
+        let endpoint: &str = ApiEndpoint::UnboundGetHostAlias.into();
+        let url = self.url(endpoint) + &uuid;
+        let parsed: models::HostAliasGet = self.send(self.client.get(url)).await?;
+        Ok(parsed.alias.ok_or(ApiError::NotFound(uuid))?)
     }
 
     pub async fn unbound_add_host_alias(
@@ -146,30 +309,41 @@ impl Opnsense {
         let endpoint: &str = ApiEndpoint::UnboundAddHostAlias.into();
         let url = self.url(endpoint);
         let payload: HashMap<&str, &models::NewHostAlias> = [("alias", new)].into_iter().collect();
-        let resp = self.client.post(url).json(&payload).send().await?;
-        let resp = resp.error_for_status()?;
-        let parsed = resp.json::<models::ApiResult>().await?;
+        let parsed: models::ApiResult = self.send(self.client.post(url).json(&payload)).await?;
 
         if parsed.result == "failed" {
-            Err(anyhow!(format!(
-                "Operation failed: {:?}",
-                parsed.validations
-            )))?
+            Err(ApiError::Validation(parsed.validations.unwrap_or_default()))?
         }
 
         parsed.uuid.ok_or(anyhow!("Failed to parse UUID"))
     }
 
+    pub async fn unbound_set_host_alias(
+        &self,
+        uuid: String,
+        update: &models::HostAliasUpdate,
+    ) -> Result<(), Error> {
+        let endpoint: &str = ApiEndpoint::UnboundSetHostAlias.into();
+        let url = self.url(endpoint) + &uuid;
+        let payload: HashMap<&str, &models::HostAliasUpdate> =
+            [("alias", update)].into_iter().collect();
+        let parsed: models::ApiResult = self.send(self.client.post(url).json(&payload)).await?;
+        if parsed.result != "saved" {
+            Err(ApiError::Validation(parsed.validations.unwrap_or_default()))?
+        }
+
+        Ok(())
+    }
+
     pub async fn unbound_del_host_alias(&self, uuid: String) -> Result<(), Error> {
         let endpoint: &str = ApiEndpoint::UnboundDelHostAlias.into();
         let url = self.url(endpoint) + &uuid;
-        let resp = self.client.post(&url).body("{}").send().await?;
-        let parsed = resp.json::<models::ApiResult>().await?;
+        let parsed: models::ApiResult = self.send(self.client.post(&url).body("{}")).await?;
+        if parsed.result == "not found" {
+            Err(ApiError::NotFound(uuid))?
+        }
         if parsed.result != "deleted" {
-            Err(anyhow!(format!(
-                "Operation failed: {:?}",
-                parsed.validations
-            )))?
+            Err(ApiError::Validation(parsed.validations.unwrap_or_default()))?
         }
 
         Ok(())
@@ -311,6 +485,66 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_unbound_reconfigure() -> Result<(), Error> {
+        let mut server = mockito::Server::new_async().await;
+        let host = format!("http://{}", server.host_with_port());
+
+        let mock = server
+            .mock::<&str>("POST", ApiEndpoint::UnboundServiceReconfigure.into())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"status": "ok"}"#)
+            .create();
+
+        let opnsense =
+            Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
+        opnsense.unbound_reconfigure().await?;
+        mock.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unbound_reconfigure_failed() -> Result<(), Error> {
+        let mut server = mockito::Server::new_async().await;
+        let host = format!("http://{}", server.host_with_port());
+
+        let mock = server
+            .mock::<&str>("POST", ApiEndpoint::UnboundServiceReconfigure.into())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"status": "failed"}"#)
+            .create();
+
+        let opnsense =
+            Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
+        assert!(opnsense.unbound_reconfigure().await.is_err());
+        mock.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unbound_restart() -> Result<(), Error> {
+        let mut server = mockito::Server::new_async().await;
+        let host = format!("http://{}", server.host_with_port());
+
+        let mock = server
+            .mock::<&str>("POST", ApiEndpoint::UnboundServiceRestart.into())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"response": "OK\n\n"}"#)
+            .create();
+
+        let opnsense =
+            Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
+        opnsense.unbound_restart().await?;
+        mock.assert();
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_unbound_get_host_overrides() -> Result<(), Error> {
         // Request a new server from the pool
@@ -320,7 +554,7 @@ mod tests {
 
         // // Create a mock
         let mock = server
-            .mock::<&str>("GET", ApiEndpoint::UnboundSearchHostOverrides.into())
+            .mock::<&str>("POST", ApiEndpoint::UnboundSearchHostOverrides.into())
             .with_status(202)
             .with_header("content-type", "application/json")
             .with_body(
@@ -348,10 +582,21 @@ mod tests {
                             "mx": "",
                             "server": "192.168.0.1",
                             "description": "some-description2"
+                        },
+                        {
+                            "uuid": "some-uuid3",
+                            "enabled": "1",
+                            "hostname": "",
+                            "domain": "some-domain",
+                            "rr": "MX (Mail server)",
+                            "mxprio": "10",
+                            "mx": "mail.some-domain",
+                            "server": "",
+                            "description": "some-description3"
                         }
                     ],
-                    "rowCount": 2,
-                    "total": 2,
+                    "rowCount": 3,
+                    "total": 3,
                     "current": 1
                 }
                 "#,
@@ -370,7 +615,11 @@ mod tests {
                     hostname: "hostname".to_string(),
                     domain: "some-domain".to_string(),
                     rr: models::HostOverrideType::A,
+                    mxprio: "".to_string(),
+                    mx: "".to_string(),
                     server: "127.0.0.1".to_string(),
+                    txtdata: "".to_string(),
+                    ttl: "".to_string(),
                     description: "some-description".to_string(),
                 },
                 models::HostOverrideRow {
@@ -379,12 +628,29 @@ mod tests {
                     hostname: "hostname".to_string(),
                     domain: "some-domain".to_string(),
                     rr: models::HostOverrideType::AAAA,
+                    mxprio: "".to_string(),
+                    mx: "".to_string(),
                     server: "192.168.0.1".to_string(),
+                    txtdata: "".to_string(),
+                    ttl: "".to_string(),
                     description: "some-description2".to_string(),
                 },
+                models::HostOverrideRow {
+                    uuid: "some-uuid3".to_string(),
+                    enabled: true,
+                    hostname: "".to_string(),
+                    domain: "some-domain".to_string(),
+                    rr: models::HostOverrideType::MX,
+                    mxprio: "10".to_string(),
+                    mx: "mail.some-domain".to_string(),
+                    server: "".to_string(),
+                    txtdata: "".to_string(),
+                    ttl: "".to_string(),
+                    description: "some-description3".to_string(),
+                },
             ],
-            row_count: 2,
-            total: 2,
+            row_count: 3,
+            total: 3,
             current: 1,
         };
 
@@ -439,12 +705,14 @@ mod tests {
             Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
         let payload = models::NewHostOverride {
             enabled: true,
-            hostname: "hostname".to_string(),
-            domain: "domain".to_string(),
+            hostname: "hostname".parse()?,
+            domain: "domain".parse()?,
             rr: models::HostOverrideType::A,
             mxprio: "".to_string(),
             mx: "".to_string(),
             server: "server".to_string(),
+            txtdata: "".to_string(),
+            ttl: "".to_string(),
             description: "description".to_string(),
         };
         opnsense.unbound_add_host_override(&payload).await?;
@@ -501,12 +769,14 @@ mod tests {
             Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
         let payload = models::NewHostOverride {
             enabled: true,
-            hostname: "hostname".to_string(),
-            domain: "domain".to_string(),
+            hostname: "hostname".parse()?,
+            domain: "domain".parse()?,
             rr: models::HostOverrideType::A,
             mxprio: "".to_string(),
             mx: "".to_string(),
             server: "server".to_string(),
+            txtdata: "".to_string(),
+            ttl: "".to_string(),
             description: "description".to_string(),
         };
         let resp = opnsense.unbound_add_host_override(&payload).await;
@@ -517,6 +787,124 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_unbound_add_host_override_txt_unsupported() -> Result<(), Error> {
+        // Request a new server from the pool
+        let mut server = mockito::Server::new_async().await;
+        let host = server.host_with_port();
+        let host = format!("http://{}", host);
+
+        let expected = r#"
+            {
+                "host": {
+                    "enabled": "1",
+                    "hostname": "hostname",
+                    "domain": "domain",
+                    "rr": "TXT",
+                    "mxprio": "",
+                    "mx": "",
+                    "server": "",
+                    "txtdata": "heritage=external-dns",
+                    "description": "description"
+                }
+            }
+        "#;
+
+        // // Create a mock
+        let mock = server
+            .mock::<&str>("POST", ApiEndpoint::UnboundAddHostOverride.into())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("accept", "application/json")
+            .with_body(
+                r#"
+                {
+                    "result": "failed",
+                    "validations": {
+                        "host.rr": "Option not in this list."
+                    }
+                }
+                "#,
+            )
+            .match_header("content-type", "application/json")
+            .match_header("accept", "application/json")
+            .match_body(Matcher::JsonString(expected.to_string()))
+            .create();
+
+        let opnsense =
+            Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
+        let payload = models::NewHostOverride {
+            enabled: true,
+            hostname: "hostname".parse()?,
+            domain: "domain".parse()?,
+            rr: models::HostOverrideType::TXT,
+            mxprio: "".to_string(),
+            mx: "".to_string(),
+            server: "".to_string(),
+            txtdata: "heritage=external-dns".to_string(),
+            ttl: "".to_string(),
+            description: "description".to_string(),
+        };
+        let resp = opnsense.unbound_add_host_override(&payload).await;
+
+        mock.assert();
+        let err = resp.unwrap_err();
+        assert!(err.to_string().contains("does not support TXT"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unbound_set_host_override() -> Result<(), Error> {
+        // Request a new server from the pool
+        let mut server = mockito::Server::new_async().await;
+        let host = server.host_with_port();
+        let host = format!("http://{}", host);
+        let uuid = "someuuid";
+
+        let expected = r#"
+            {
+                "host": {
+                    "description": "description"
+                }
+            }
+        "#;
+        let endpoint = <ApiEndpoint as Into<&str>>::into(ApiEndpoint::UnboundSetHostOverride)
+            .to_string()
+            + uuid;
+
+        let mock = server
+            .mock::<&str>("POST", &endpoint)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("accept", "application/json")
+            .with_body(
+                r#"
+                {
+                    "result": "saved"
+                }
+                "#,
+            )
+            .match_header("content-type", "application/json")
+            .match_header("accept", "application/json")
+            .match_body(Matcher::JsonString(expected.to_string()))
+            .create();
+
+        let opnsense =
+            Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
+        let update = models::HostOverrideUpdate {
+            description: Some("description".to_string()),
+            ..Default::default()
+        };
+        opnsense
+            .unbound_set_host_override(uuid.to_string(), &update)
+            .await?;
+
+        mock.assert();
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_unbound_del_host_override() -> Result<(), Error> {
         // Request a new server from the pool
@@ -548,7 +936,7 @@ mod tests {
 
         let opnsense =
             Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
-        let resp = opnsense.unbound_del_host_override(uuid.to_string()).await?;
+        opnsense.unbound_del_host_override(uuid.to_string()).await?;
 
         mock.assert();
 
@@ -589,7 +977,65 @@ mod tests {
         let resp = opnsense.unbound_del_host_override(uuid.to_string()).await;
 
         mock.assert();
-        assert!(resp.is_err());
+        let err = resp.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ApiError>(),
+            Some(ApiError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unbound_get_host_overrides_unauthorized() -> Result<(), Error> {
+        // Request a new server from the pool
+        let mut server = mockito::Server::new_async().await;
+        let host = server.host_with_port();
+        let host = format!("http://{}", host);
+
+        let mock = server
+            .mock::<&str>("POST", ApiEndpoint::UnboundSearchHostOverrides.into())
+            .with_status(401)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"status": 401, "message": "Authentication Failed"}"#)
+            .create();
+
+        let opnsense =
+            Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
+        let resp = opnsense.unbound_get_host_overrides().await;
+
+        mock.assert();
+        let err = resp.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ApiError>(),
+            Some(ApiError::Unauthorized(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unbound_get_host_aliases_invalid_name() -> Result<(), Error> {
+        let mut server = mockito::Server::new_async().await;
+        let host = format!("http://{}", server.host_with_port());
+
+        // A row named by hand with something no DNS name allows still lists.
+        let mock = server
+            .mock::<&str>("POST", ApiEndpoint::UnboundSearchHostAliases.into())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"rows": [{"uuid": "some-uuid", "enabled": "1", "host": "some-host",
+                    "hostname": "not a name", "domain": "some-domain",
+                    "description": ""}], "rowCount": 1, "total": 1, "current": 1}"#,
+            )
+            .create();
+
+        let opnsense =
+            Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
+        let resp = opnsense.unbound_get_host_aliases().await?;
+        assert_eq!(resp.rows[0].hostname, "not a name");
+        mock.assert();
 
         Ok(())
     }
@@ -603,7 +1049,7 @@ mod tests {
 
         // // Create a mock
         let mock = server
-            .mock::<&str>("GET", ApiEndpoint::UnboundSearchHostAliases.into())
+            .mock::<&str>("POST", ApiEndpoint::UnboundSearchHostAliases.into())
             .with_status(202)
             .with_header("content-type", "application/json")
             .with_body(
@@ -694,10 +1140,10 @@ mod tests {
             Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
         let payload = models::NewHostAlias {
             description: "some-description".to_string(),
-            domain: "some-domain".to_string(),
+            domain: "some-domain".parse()?,
             enabled: true,
             host: "some-host-uuid".to_string(),
-            hostname: "some-hostname".to_string(),
+            hostname: "some-hostname".parse()?,
         };
 
         let uuid = opnsense.unbound_add_host_alias(&payload).await?;
@@ -752,10 +1198,10 @@ mod tests {
             Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
         let payload = models::NewHostAlias {
             description: "some-description".to_string(),
-            domain: "some-domain".to_string(),
+            domain: "some-domain".parse()?,
             enabled: true,
             host: "a-nonexistent-uuid".to_string(),
-            hostname: "some-hostname".to_string(),
+            hostname: "some-hostname".parse()?,
         };
         let resp = opnsense.unbound_add_host_alias(&payload).await;
 
@@ -765,6 +1211,130 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_unbound_set_host_alias_failed() -> Result<(), Error> {
+        // Request a new server from the pool
+        let mut server = mockito::Server::new_async().await;
+        let host = server.host_with_port();
+        let host = format!("http://{}", host);
+        let uuid = "someuuid";
+
+        let expected = r#"
+            {
+                "alias": {
+                    "enabled": "1"
+                }
+            }
+        "#;
+        let endpoint =
+            <ApiEndpoint as Into<&str>>::into(ApiEndpoint::UnboundSetHostAlias).to_string() + uuid;
+
+        let mock = server
+            .mock::<&str>("POST", &endpoint)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("accept", "application/json")
+            .with_body(
+                r#"
+                {
+                    "result": "failed",
+                    "validations": {
+                        "alias.host": "Option not in this list."
+                    }
+                }
+                "#,
+            )
+            .match_header("content-type", "application/json")
+            .match_header("accept", "application/json")
+            .match_body(Matcher::JsonString(expected.to_string()))
+            .create();
+
+        let opnsense =
+            Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
+        let update = models::HostAliasUpdate {
+            enabled: Some(true),
+            ..Default::default()
+        };
+        let resp = opnsense
+            .unbound_set_host_alias(uuid.to_string(), &update)
+            .await;
+
+        mock.assert();
+        assert!(resp.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unbound_get_host_alias() -> Result<(), Error> {
+        // Request a new server from the pool
+        let mut server = mockito::Server::new_async().await;
+        let host = server.host_with_port();
+        let host = format!("http://{}", host);
+
+        let endpoint =
+            <ApiEndpoint as Into<&str>>::into(ApiEndpoint::UnboundGetHostAlias).to_string();
+
+        let mock = server
+            .mock::<&str>("GET", &(endpoint.clone() + "someuuid"))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"
+                {
+                    "alias": {
+                        "enabled": "1",
+                        "host": {
+                            "parent-uuid": {"value": "api.example.com", "selected": 1},
+                            "other-uuid": {"value": "db.example.com", "selected": 0}
+                        },
+                        "hostname": "www",
+                        "domain": "example.com",
+                        "description": "_ouw_"
+                    }
+                }
+                "#,
+            )
+            .match_header("accept", "application/json")
+            .create();
+        let missing = server
+            .mock::<&str>("GET", &(endpoint + "gone"))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body("[]")
+            .create();
+
+        let opnsense =
+            Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
+        let alias = opnsense
+            .unbound_get_host_alias("someuuid".to_string())
+            .await?;
+        assert_eq!(
+            alias,
+            models::HostAliasItem {
+                enabled: true,
+                host: Some("parent-uuid".to_string()),
+                hostname: "www".to_string(),
+                domain: "example.com".to_string(),
+                description: "_ouw_".to_string(),
+            }
+        );
+
+        let err = opnsense
+            .unbound_get_host_alias("gone".to_string())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ApiError>(),
+            Some(ApiError::NotFound(_))
+        ));
+
+        mock.assert();
+        missing.assert();
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_unbound_del_host_alias() -> Result<(), Error> {
         // Request a new server from the pool
@@ -795,7 +1365,7 @@ mod tests {
 
         let opnsense =
             Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
-        let resp = opnsense.unbound_del_host_alias(uuid.to_string()).await?;
+        opnsense.unbound_del_host_alias(uuid.to_string()).await?;
 
         mock.assert();
 
@@ -839,4 +1409,83 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_unbound_search_all_host_aliases_pages() -> Result<(), Error> {
+        let mut server = mockito::Server::new_async().await;
+        let host = format!("http://{}", server.host_with_port());
+        let row = |uuid: &str| {
+            format!(
+                r#"{{"uuid": "{}", "enabled": "1", "host": "api.example.com",
+                    "hostname": "{}", "domain": "example.com", "description": "_ouw_"}}"#,
+                uuid, uuid
+            )
+        };
+
+        let first = server
+            .mock::<&str>("POST", ApiEndpoint::UnboundSearchHostAliases.into())
+            .match_body(Matcher::JsonString(
+                r#"{"current": 1, "rowCount": 2, "searchPhrase": "_ouw_", "sort": {"hostname": "asc"}}"#
+                    .to_string(),
+            ))
+            .with_body(format!(
+                r#"{{"rows": [{}, {}], "rowCount": 2, "total": 3, "current": 1}}"#,
+                row("a"),
+                row("b")
+            ))
+            .create();
+        let second = server
+            .mock::<&str>("POST", ApiEndpoint::UnboundSearchHostAliases.into())
+            .match_body(Matcher::PartialJsonString(r#"{"current": 2}"#.to_string()))
+            .with_body(format!(
+                r#"{{"rows": [{}], "rowCount": 1, "total": 3, "current": 2}}"#,
+                row("c")
+            ))
+            .create();
+
+        let opnsense =
+            Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
+        let request = models::SearchRequest::default()
+            .row_count(2)
+            .search_phrase("_ouw_")
+            .sort("hostname", models::SortOrder::Asc);
+        let resp = opnsense.unbound_search_all_host_aliases(&request).await?;
+        assert_eq!(
+            resp.rows
+                .iter()
+                .map(|i| i.uuid.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(resp.row_count, 3);
+        assert_eq!(resp.total, 3);
+
+        first.assert();
+        second.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unbound_search_host_overrides_one_page() -> Result<(), Error> {
+        let mut server = mockito::Server::new_async().await;
+        let host = format!("http://{}", server.host_with_port());
+
+        let mock = server
+            .mock::<&str>("POST", ApiEndpoint::UnboundSearchHostOverrides.into())
+            .match_body(Matcher::PartialJsonString(r#"{"current": 4}"#.to_string()))
+            .with_body(r#"{"rows": [], "rowCount": 0, "total": 1000, "current": 4}"#)
+            .expect(1)
+            .create();
+
+        let opnsense =
+            Opnsense::new(&host, Some(SECRET.to_string()), Some(KEY.to_string()), true).unwrap();
+        let resp = opnsense
+            .unbound_search_host_overrides(&models::SearchRequest::default().page(4))
+            .await?;
+        assert_eq!(resp.total, 1000);
+        mock.assert();
+
+        Ok(())
+    }
 }
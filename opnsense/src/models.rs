@@ -1,9 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use serde::Serializer;
 use serde::de;
 use serde::{Deserialize, Serialize};
 
+use crate::dns_name::DnsName;
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum StatusType {
     #[serde(rename = "running")]
@@ -17,6 +19,16 @@ pub struct Status {
     pub status: StatusType,
 }
 
+/// The result of a service action such as a reconfigure or restart. Some
+/// actions only report `status` and others only the configd `response`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ServiceResult {
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub response: String,
+}
+
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum HostOverrideType {
     #[serde(rename = "A (IPv4 address)")]
@@ -25,6 +37,8 @@ pub enum HostOverrideType {
     AAAA,
     #[serde(rename = "MX (Mail server)")]
     MX,
+    #[serde(rename = "TXT (Text records)")]
+    TXT,
 }
 
 impl Serialize for HostOverrideType {
@@ -36,6 +50,7 @@ impl Serialize for HostOverrideType {
             HostOverrideType::A => "A",
             HostOverrideType::AAAA => "AAAA",
             HostOverrideType::MX => "MX",
+            HostOverrideType::TXT => "TXT",
         };
         serializer.serialize_str(s)
     }
@@ -49,21 +64,25 @@ pub struct HostOverrideRow {
         deserialize_with = "deserialize_bool"
     )]
     pub enabled: bool,
+    /// Kept as listed, so a single row with a name `DnsName` rejects does not
+    /// fail the whole search.
     pub hostname: String,
     pub domain: String,
     pub rr: HostOverrideType,
+    pub mxprio: String,
+    pub mx: String,
     pub server: String,
+    /// Only reported by OPNsense releases with TXT host override support.
+    #[serde(default)]
+    pub txtdata: String,
+    /// Only reported by OPNsense releases with per-override TTLs. Empty uses
+    /// Unbound's default.
+    #[serde(default)]
+    pub ttl: String,
     pub description: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-pub struct HostOverride {
-    pub rows: Vec<HostOverrideRow>,
-    #[serde(rename = "rowCount")]
-    pub row_count: u64,
-    pub total: u64,
-    pub current: u64,
-}
+pub type HostOverride = SearchResult<HostOverrideRow>;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NewHostOverride {
@@ -72,15 +91,36 @@ pub struct NewHostOverride {
         deserialize_with = "deserialize_bool"
     )]
     pub enabled: bool,
-    pub hostname: String,
-    pub domain: String,
+    pub hostname: DnsName,
+    pub domain: DnsName,
     pub rr: HostOverrideType,
     pub mxprio: String,
     pub mx: String,
     pub server: String,
+    /// Omitted when empty so releases without TXT support accept the payload.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub txtdata: String,
+    /// Omitted when empty so releases without per-override TTLs accept the
+    /// payload.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub ttl: String,
     pub description: String,
 }
 
+/// Partial update for an existing host override. Unset fields are left as is.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct HostOverrideUpdate {
+    #[serde(
+        serialize_with = "serialize_optional_bool",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct HostAliasRow {
     pub uuid: String,
@@ -90,33 +130,146 @@ pub struct HostAliasRow {
     )]
     pub enabled: bool,
     pub host: String,
+    /// Kept as listed, so a single row with a name `DnsName` rejects does not
+    /// fail the whole search.
     pub hostname: String,
     pub domain: String,
     pub description: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-pub struct HostAlias {
-    pub rows: Vec<HostAliasRow>,
-    #[serde(rename = "rowCount")]
-    pub row_count: u64,
-    pub total: u64,
-    pub current: u64,
-}
+pub type HostAlias = SearchResult<HostAliasRow>;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NewHostAlias {
     pub description: String,
-    pub domain: String,
+    pub domain: DnsName,
     #[serde(
         serialize_with = "serialize_bool",
         deserialize_with = "deserialize_bool"
     )]
     pub enabled: bool,
-    pub hostname: String,
+    pub hostname: DnsName,
     pub host: String,
 }
 
+/// Partial update for an existing host alias. Unset fields are left as is.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct HostAliasUpdate {
+    #[serde(
+        serialize_with = "serialize_optional_bool",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub enabled: Option<bool>,
+    /// The UUID of the parent host override.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// A single host alias, as returned by `getHostAlias`. Unlike the search, this
+/// identifies the parent host override by UUID rather than by name.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostAliasItem {
+    #[serde(deserialize_with = "deserialize_bool")]
+    pub enabled: bool,
+    /// The parent, if one is selected.
+    #[serde(deserialize_with = "deserialize_selected")]
+    pub host: Option<Uuid>,
+    pub hostname: String,
+    pub domain: String,
+    pub description: String,
+}
+
+/// The body of a `get` request, which is an empty list for an unknown UUID.
+#[derive(Deserialize, Debug)]
+pub(crate) struct HostAliasGet {
+    #[serde(default)]
+    pub alias: Option<HostAliasItem>,
+}
+
+/// Rows requested per page unless set otherwise.
+pub const DEFAULT_PAGE_SIZE: u64 = 500;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    #[serde(rename = "asc")]
+    Asc,
+    #[serde(rename = "desc")]
+    Desc,
+}
+
+/// The parameters of a `search*` endpoint, built up from the defaults:
+///
+/// ```
+/// use opnsense::models::{SearchRequest, SortOrder};
+///
+/// let request = SearchRequest::default()
+///     .search_phrase("_ouw_")
+///     .sort("hostname", SortOrder::Asc)
+///     .row_count(100);
+/// assert_eq!(request.current, 1);
+/// ```
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SearchRequest {
+    /// The page, counting from 1.
+    pub current: u64,
+    /// Rows per page.
+    #[serde(rename = "rowCount")]
+    pub row_count: u64,
+    /// Only rows with a field containing this.
+    #[serde(rename = "searchPhrase", skip_serializing_if = "String::is_empty")]
+    pub search_phrase: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub sort: BTreeMap<String, SortOrder>,
+}
+
+impl Default for SearchRequest {
+    fn default() -> Self {
+        SearchRequest {
+            current: 1,
+            row_count: DEFAULT_PAGE_SIZE,
+            search_phrase: String::new(),
+            sort: BTreeMap::new(),
+        }
+    }
+}
+
+impl SearchRequest {
+    pub fn page(mut self, current: u64) -> Self {
+        self.current = current;
+        self
+    }
+
+    pub fn row_count(mut self, row_count: u64) -> Self {
+        self.row_count = row_count;
+        self
+    }
+
+    pub fn search_phrase(mut self, search_phrase: impl Into<String>) -> Self {
+        self.search_phrase = search_phrase.into();
+        self
+    }
+
+    pub fn sort(mut self, field: impl Into<String>, order: SortOrder) -> Self {
+        self.sort.insert(field.into(), order);
+        self
+    }
+}
+
+/// One page of a `search*` endpoint, or every page from `current` on once
+/// collected.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult<T> {
+    pub rows: Vec<T>,
+    /// The rows on this page.
+    #[serde(rename = "rowCount")]
+    pub row_count: u64,
+    /// The rows matching the search, across every page.
+    pub total: u64,
+    pub current: u64,
+}
+
 pub type Uuid = String;
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ApiResult {
@@ -138,6 +291,42 @@ where
     serializer.serialize_str(res)
 }
 
+fn serialize_optional_bool<S>(value: &Option<bool>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match value {
+        Some(value) => serialize_bool(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// An option of a relation field, e.g. `{"value": "api.example.com", "selected": 1}`.
+#[derive(Deserialize)]
+struct SelectOption {
+    selected: Selected,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Selected {
+    Int(u8),
+    Bool(bool),
+}
+
+/// The key of the selected option of a relation field, which is listed as a
+/// map of every possible option.
+fn deserialize_selected<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let options: HashMap<String, SelectOption> = de::Deserialize::deserialize(deserializer)?;
+    Ok(options
+        .into_iter()
+        .find(|(_, option)| matches!(option.selected, Selected::Int(1) | Selected::Bool(true)))
+        .map(|(key, _)| key))
+}
+
 fn deserialize_bool<'de, D>(deserializer: D) -> Result<bool, D::Error>
 where
     D: de::Deserializer<'de>,
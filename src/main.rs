@@ -3,19 +3,37 @@ extern crate rocket;
 
 extern crate opnsense;
 
+use anyhow::Context;
 use clap::Parser;
 use log::debug;
+use regex::Regex;
 use std::env;
+use std::time::Duration;
 
 mod web;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Domains supported by this instance.
+    /// Domains supported by this instance. Names are split into the hostname
+    /// and domain of host overrides at these, so making changes needs one.
     #[arg(short, long = "domain", env)]
     domains: Vec<String>,
 
+    /// Domains excluded from this instance, even when within --domain.
+    #[arg(long = "exclude-domain", env)]
+    exclude_domains: Vec<String>,
+
+    /// Regular expression for the names supported by this instance. Overrides
+    /// --domain and --exclude-domain.
+    #[arg(long, env)]
+    regex_domain_filter: Option<Regex>,
+
+    /// Regular expression for names this instance does not support. Like
+    /// --regex-domain-filter, overrides --domain and --exclude-domain.
+    #[arg(long, env)]
+    regex_domain_exclusion: Option<Regex>,
+
     /// Increase log level for more debug info.
     #[arg(long, env, default_value = "info")]
     log_level: log::Level,
@@ -35,10 +53,47 @@ struct Args {
     /// Ignore HTTPS certificate errors.
     #[arg(long, action, env)]
     insecure: bool,
+
+    /// Store external-dns TXT registry records as TXT host overrides instead of
+    /// synthesizing them. Requires an OPNsense release with TXT support.
+    #[arg(long, action, env)]
+    native_txt_registry: bool,
+
+    /// TXT registry prefix. Must match external-dns' --txt-prefix.
+    #[arg(long, env, default_value = "")]
+    txt_prefix: String,
+
+    /// TXT registry suffix. Must match external-dns' --txt-suffix.
+    #[arg(long, env, default_value = "")]
+    txt_suffix: String,
+
+    /// TTL reported for records without one, such as those created by hand.
+    #[arg(long, env, default_value_t = web::DEFAULT_TTL)]
+    default_ttl: i64,
+
+    /// Also apply record TTLs to the host overrides in Unbound. Requires an
+    /// OPNsense release with per-override TTLs.
+    #[arg(long, action, env)]
+    override_ttl: bool,
+
+    /// How to make changes live in Unbound.
+    #[arg(long, env, value_enum, default_value_t)]
+    apply_mode: web::ApplyMode,
+
+    /// Seconds to collect changes for before making them live, so bursts of
+    /// batches only disrupt Unbound once.
+    #[arg(long, env, default_value_t = 10)]
+    apply_window: u64,
+
+    /// Seconds to serve the listing of records from cache. Our own changes
+    /// are visible immediately; those made elsewhere only after this long.
+    /// Zero disables the cache.
+    #[arg(long, env, default_value_t = 30)]
+    records_cache_ttl: u64,
 }
 
 #[rocket::main]
-async fn main() -> Result<(), rocket::Error> {
+async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     env_logger::Builder::new()
         .filter_level(args.log_level.to_level_filter())
@@ -53,6 +108,9 @@ async fn main() -> Result<(), rocket::Error> {
     for i in &args.domains {
         debug!("Found included domain: {}", &i);
     }
+    for i in &args.exclude_domains {
+        debug!("Found excluded domain: {}", &i);
+    }
 
     let opnsense = opnsense::Opnsense::new(
         &args.opnsense_url,
@@ -60,21 +118,59 @@ async fn main() -> Result<(), rocket::Error> {
         args.opnsense_secret,
         args.insecure,
     )
-    .unwrap();
+    .context("Failed to create the OPNsense client")?;
+
+    let filter = web::DomainFilter {
+        include: args.domains,
+        exclude: args.exclude_domains,
+        regex_include: args.regex_domain_filter,
+        regex_exclude: args.regex_domain_exclusion,
+    };
+
+    let registry = web::TxtRegistry {
+        native: args.native_txt_registry,
+        prefix: args.txt_prefix,
+        suffix: args.txt_suffix,
+    };
+
+    let ttl = web::TtlPolicy {
+        default: args.default_ttl,
+        host_overrides: args.override_ttl,
+    };
+
+    let pipeline = web::Pipeline::new(
+        Duration::from_secs(args.records_cache_ttl),
+        args.apply_mode,
+        Duration::from_secs(args.apply_window),
+    );
+    rocket::tokio::spawn(pipeline.applier.clone().run(opnsense.clone()));
 
     let _rocket = rocket::build()
         .mount(
             "/",
             routes![
                 web::healthz,
+                web::status,
                 web::negotiate,
                 web::records_get,
                 web::records_post,
                 web::adjust_endpoints,
             ],
         )
-        .manage(args.domains)
+        .register(
+            "/",
+            catchers![
+                web::not_found,
+                web::unprocessable_entity,
+                web::default_catcher,
+            ],
+        )
+        .attach(web::flush_on_shutdown())
+        .manage(filter)
         .manage(opnsense)
+        .manage(registry)
+        .manage(ttl)
+        .manage(pipeline)
         .launch()
         .await?;
 
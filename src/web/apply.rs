@@ -0,0 +1,238 @@
+use anyhow::Error;
+use opnsense::Opnsense;
+use rocket::tokio::sync::Notify;
+use rocket::tokio::time;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How changes saved through the settings API are made live in Unbound. Until
+/// then they only exist in `config.xml`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApplyMode {
+    /// Regenerate and reload Unbound's configuration, as Apply in the UI does.
+    #[default]
+    Reconfigure,
+    /// Restart Unbound, which also drops its cache.
+    Restart,
+}
+
+impl ApplyMode {
+    pub async fn apply(&self, opnsense: &Opnsense) -> Result<(), Error> {
+        match self {
+            ApplyMode::Reconfigure => opnsense.unbound_reconfigure().await,
+            ApplyMode::Restart => opnsense.unbound_restart().await,
+        }
+    }
+}
+
+/// How long to wait after a failed apply before the first retry, regardless of
+/// the window. Every further failure doubles it, up to `MAX_RETRY_DELAY`.
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// What the applier has done so far, as reported by `/status`.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplierStatus {
+    /// Changes saved to OPNsense but not yet live in Unbound.
+    #[serde(rename = "pendingChanges")]
+    pub pending: usize,
+    /// When changes were last made live, in seconds since the Unix epoch.
+    #[serde(rename = "lastApplied")]
+    pub last_applied: Option<u64>,
+}
+
+/// Makes changes live in Unbound in the background.
+///
+/// Applying flushes Unbound's cache and briefly disrupts resolution, so rather
+/// than once per batch, every batch marks the configuration dirty and the
+/// applier runs at most once per `window`, covering everything marked since.
+///
+/// The count of pending changes only lives in memory, so it applies once when
+/// it starts, for changes a previous process saved but never applied, and
+/// flushes whatever is pending when Rocket shuts down.
+pub struct Applier {
+    mode: ApplyMode,
+    window: Duration,
+    status: Mutex<ApplierStatus>,
+    /// Whether there may be unapplied changes not counted as pending.
+    stale: AtomicBool,
+    dirty: Notify,
+}
+
+impl Applier {
+    pub fn new(mode: ApplyMode, window: Duration) -> Self {
+        Applier {
+            mode,
+            window,
+            status: Mutex::new(ApplierStatus::default()),
+            stale: AtomicBool::new(false),
+            dirty: Notify::new(),
+        }
+    }
+
+    pub fn status(&self) -> ApplierStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Record `changes` saved to OPNsense which still need applying.
+    pub fn mark_dirty(&self, changes: usize) {
+        if changes == 0 {
+            return;
+        }
+        self.status.lock().unwrap().pending += changes;
+        self.dirty.notify_one();
+    }
+
+    /// Apply whatever is pending. A failed apply leaves it pending for the
+    /// next attempt.
+    pub async fn apply_pending(&self, opnsense: &Opnsense) -> Result<(), Error> {
+        let applying = self.status().pending;
+        if applying == 0 && !self.stale.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        self.mode.apply(opnsense).await?;
+        self.stale.store(false, Ordering::SeqCst);
+        let mut status = self.status.lock().unwrap();
+        // Changes marked while applying may have been saved too late to be
+        // included, so they stay pending.
+        status.pending = status.pending.saturating_sub(applying);
+        status.last_applied = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|i| i.as_secs());
+        Ok(())
+    }
+
+    /// Apply pending changes until the process exits, starting with one apply
+    /// for whatever an earlier process left. Each apply waits out the window
+    /// first, so a burst of batches is applied once.
+    ///
+    /// Failed applies are retried with a backoff, so a firewall that is down
+    /// is not hammered even with a zero window.
+    pub async fn run(self: Arc<Self>, opnsense: Opnsense) {
+        self.stale.store(true, Ordering::SeqCst);
+        self.dirty.notify_one();
+        let mut retry_delay = MIN_RETRY_DELAY;
+        loop {
+            self.dirty.notified().await;
+            time::sleep(self.window).await;
+            match self.apply_pending(&opnsense).await {
+                Ok(()) => retry_delay = MIN_RETRY_DELAY,
+                Err(e) => {
+                    error!(
+                        "Failed to apply changes, retrying in {:?}: {:#}",
+                        retry_delay, e
+                    );
+                    time::sleep(retry_delay).await;
+                    retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                    self.dirty.notify_one();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn test_apply_pending() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = Opnsense::new(&server.url(), None, None, true).unwrap();
+        let failed = server
+            .mock("POST", "/api/unbound/service/reconfigure")
+            .with_body(r#"{"status": "failed"}"#)
+            .create_async()
+            .await;
+
+        let applier = Applier::new(ApplyMode::Reconfigure, Duration::ZERO);
+        applier.mark_dirty(2);
+        applier.mark_dirty(1);
+        assert!(applier.apply_pending(&opnsense).await.is_err());
+        assert_eq!(applier.status().pending, 3);
+        assert_eq!(applier.status().last_applied, None);
+        failed.remove_async().await;
+
+        server
+            .mock("POST", "/api/unbound/service/reconfigure")
+            .with_body(r#"{"status": "ok"}"#)
+            .create_async()
+            .await;
+        applier.apply_pending(&opnsense).await.unwrap();
+        assert_eq!(applier.status().pending, 0);
+        assert!(applier.status().last_applied.is_some());
+    }
+
+    #[rocket::async_test]
+    async fn test_run_applies_on_start() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = Opnsense::new(&server.url(), None, None, true).unwrap();
+        let reconfigure = server
+            .mock("POST", "/api/unbound/service/reconfigure")
+            .with_body(r#"{"status": "ok"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        // Nothing is pending, but a previous process may have left changes.
+        let applier = Arc::new(Applier::new(ApplyMode::Reconfigure, Duration::ZERO));
+        let task = rocket::tokio::spawn(applier.clone().run(opnsense));
+        time::sleep(Duration::from_millis(200)).await;
+        task.abort();
+
+        reconfigure.assert_async().await;
+        assert!(applier.status().last_applied.is_some());
+    }
+
+    #[rocket::async_test]
+    async fn test_run_backs_off() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = Opnsense::new(&server.url(), None, None, true).unwrap();
+        let failed = server
+            .mock("POST", "/api/unbound/service/reconfigure")
+            .with_body(r#"{"status": "failed"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        // Even without a window, a failure waits before trying again.
+        let applier = Arc::new(Applier::new(ApplyMode::Reconfigure, Duration::ZERO));
+        let task = rocket::tokio::spawn(applier.clone().run(opnsense));
+        applier.mark_dirty(1);
+        time::sleep(Duration::from_millis(300)).await;
+        task.abort();
+
+        failed.assert_async().await;
+        assert_eq!(applier.status().pending, 1);
+    }
+
+    #[rocket::async_test]
+    async fn test_run_coalesces() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = Opnsense::new(&server.url(), None, None, true).unwrap();
+        let reconfigure = server
+            .mock("POST", "/api/unbound/service/reconfigure")
+            .with_body(r#"{"status": "ok"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let applier = Arc::new(Applier::new(
+            ApplyMode::Reconfigure,
+            Duration::from_millis(100),
+        ));
+        let task = rocket::tokio::spawn(applier.clone().run(opnsense));
+        for _ in 0..3 {
+            applier.mark_dirty(1);
+        }
+        time::sleep(Duration::from_millis(400)).await;
+        task.abort();
+
+        reconfigure.assert_async().await;
+        assert_eq!(applier.status().pending, 0);
+    }
+}
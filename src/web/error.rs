@@ -0,0 +1,170 @@
+use crate::web::models::{Record, RecordType};
+use anyhow::Error;
+use opnsense::ApiError;
+use opnsense::dns_name::DnsName;
+use rocket::http::Status;
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::{Request, Response};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+/// The endpoint a failed change was for. Attached to errors as context so the
+/// response can name the record.
+#[derive(Debug, Clone)]
+pub struct FailedRecord {
+    pub action: &'static str,
+    pub dns_name: DnsName,
+    pub record_type: RecordType,
+}
+
+impl FailedRecord {
+    pub fn new(action: &'static str, record: &Record) -> Self {
+        FailedRecord {
+            action,
+            dns_name: record.dns_name.clone(),
+            record_type: record.record_type.clone(),
+        }
+    }
+}
+
+impl fmt::Display for FailedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to {} {:?} {}",
+            self.action, self.record_type, self.dns_name
+        )
+    }
+}
+
+/// The JSON body of every error response.
+#[derive(Serialize, Debug)]
+pub struct ErrorBody {
+    pub error: String,
+    #[serde(rename = "dnsName", skip_serializing_if = "Option::is_none")]
+    pub dns_name: Option<DnsName>,
+    #[serde(rename = "recordType", skip_serializing_if = "Option::is_none")]
+    pub record_type: Option<RecordType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validations: Option<HashMap<String, String>>,
+}
+
+impl ErrorBody {
+    pub fn new(error: String) -> Self {
+        ErrorBody {
+            error,
+            dns_name: None,
+            record_type: None,
+            validations: None,
+        }
+    }
+}
+
+/// A failed webhook request, answered with a status matching the cause and
+/// an `ErrorBody`.
+#[derive(Debug)]
+pub struct WebhookError {
+    pub status: Status,
+    pub error: Error,
+}
+
+impl From<Error> for WebhookError {
+    fn from(error: Error) -> Self {
+        let status = match error.chain().find_map(|e| e.downcast_ref::<ApiError>()) {
+            Some(ApiError::Transport(_)) => Status::ServiceUnavailable,
+            Some(ApiError::Unauthorized(_)) => Status::BadGateway,
+            Some(ApiError::NotFound(_)) => Status::NotFound,
+            Some(ApiError::Validation(_)) | Some(ApiError::Unsupported(_)) => {
+                Status::UnprocessableEntity
+            }
+            None => Status::InternalServerError,
+        };
+
+        WebhookError { status, error }
+    }
+}
+
+impl WebhookError {
+    pub fn body(&self) -> ErrorBody {
+        let mut body = ErrorBody::new(format!("{:#}", self.error));
+        if let Some(record) = self.error.downcast_ref::<FailedRecord>() {
+            body.dns_name = Some(record.dns_name.clone());
+            body.record_type = Some(record.record_type.clone());
+        }
+        if let Some(ApiError::Validation(validations)) = self
+            .error
+            .chain()
+            .find_map(|e| e.downcast_ref::<ApiError>())
+        {
+            body.validations = Some(validations.clone());
+        }
+
+        body
+    }
+}
+
+impl<'r> Responder<'r, 'static> for WebhookError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        error!("{:?}", self.error);
+        Response::build_from(Json(self.body()).respond_to(request)?)
+            .status(self.status)
+            .ok()
+    }
+}
+
+#[catch(404)]
+pub fn not_found(request: &Request) -> Json<ErrorBody> {
+    Json(ErrorBody::new(format!("No route for {}", request.uri())))
+}
+
+#[catch(422)]
+pub fn unprocessable_entity() -> Json<ErrorBody> {
+    Json(ErrorBody::new(
+        "The request body is not valid for this endpoint".to_string(),
+    ))
+}
+
+#[catch(default)]
+pub fn default_catcher(status: Status, _request: &Request) -> Json<ErrorBody> {
+    Json(ErrorBody::new(status.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_validation_names_record() {
+        let record = Record {
+            dns_name: "api.example.com".parse().unwrap(),
+            targets: vec!["127.0.0.1".to_string()],
+            record_type: RecordType::A,
+            set_identifier: "".to_string(),
+            record_ttl: 60,
+            labels: Default::default(),
+            provider_specific: vec![],
+        };
+        let validations = HashMap::from([("host.server".to_string(), "invalid".to_string())]);
+        let error = Err::<(), _>(ApiError::Validation(validations.clone()))
+            .context(FailedRecord::new("create", &record))
+            .context("Failed to update records")
+            .unwrap_err();
+
+        let error = WebhookError::from(error);
+        assert_eq!(error.status, Status::UnprocessableEntity);
+        let body = error.body();
+        assert_eq!(body.dns_name, Some(record.dns_name));
+        assert_eq!(body.record_type, Some(RecordType::A));
+        assert_eq!(body.validations, Some(validations));
+    }
+
+    #[test]
+    fn test_unclassified_is_internal() {
+        let error = WebhookError::from(anyhow::anyhow!("boom"));
+        assert_eq!(error.status, Status::InternalServerError);
+        assert_eq!(error.body().dns_name, None);
+    }
+}
@@ -0,0 +1,224 @@
+use crate::web::models::{Filters, Record, UpdateRecords};
+use anyhow::{Error, anyhow};
+use opnsense::dns_name::DnsName;
+use regex::Regex;
+
+/// The names this webhook is allowed to manage, following external-dns'
+/// `DomainFilter` semantics.
+///
+/// When either regular expression is set, the regular expressions take
+/// precedence and the domain lists are ignored. A missing `regex_include` then
+/// matches every name.
+#[derive(Debug, Clone, Default)]
+pub struct DomainFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub regex_include: Option<Regex>,
+    pub regex_exclude: Option<Regex>,
+}
+
+impl DomainFilter {
+    pub fn matches(&self, dns_name: &DnsName) -> bool {
+        let dns_name = dns_name.as_str();
+        if self.regex_include.is_some() || self.regex_exclude.is_some() {
+            return self
+                .regex_include
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(dns_name))
+                && !self
+                    .regex_exclude
+                    .as_ref()
+                    .is_some_and(|regex| regex.is_match(dns_name));
+        }
+
+        match_domains(&self.include, dns_name, true)
+            && !match_domains(&self.exclude, dns_name, false)
+    }
+
+    /// Drop, with a warning, every record outside of the filter.
+    pub fn retain(&self, records: &mut Vec<Record>) {
+        records.retain(|record| {
+            let matches = self.matches(&record.dns_name);
+            if !matches {
+                warn!("Ignoring {} outside of the domain filter", record.dns_name);
+            }
+            matches
+        });
+    }
+
+    pub fn retain_changes(&self, changes: &mut UpdateRecords) {
+        self.retain(&mut changes.create);
+        self.retain(&mut changes.update_old);
+        self.retain(&mut changes.update_new);
+        self.retain(&mut changes.delete);
+    }
+
+    /// Split `dns_name` into the hostname and domain of a host override, at
+    /// the longest configured `--domain` it falls within.
+    ///
+    /// A configured domain itself is its zone apex, with an empty hostname.
+    /// Wildcards always use `*` as the hostname, as OPNsense requires. Without
+    /// any configured domains there is nowhere to split, so it is an error.
+    pub fn split(&self, dns_name: &DnsName) -> Result<(DnsName, DnsName), Error> {
+        if self.domains().next().is_none() {
+            Err(anyhow!(
+                "Cannot split {} into a hostname and domain without --domain",
+                dns_name
+            ))?
+        }
+        if let Some(("*", domain)) = dns_name.split_first() {
+            if !self.domains().any(|i| domain.is_within(&i)) {
+                Err(anyhow!("{} is not within any configured domain", dns_name))?
+            }
+            return Ok(("*".parse()?, domain));
+        }
+
+        self.domains()
+            .filter_map(|domain| {
+                let hostname = dns_name.relative_to(&domain)?;
+                Some((hostname, domain))
+            })
+            .max_by_key(|(_, domain)| domain.as_str().len())
+            .ok_or_else(|| anyhow!("{} is not within any configured domain", dns_name))
+    }
+
+    fn domains(&self) -> impl Iterator<Item = DnsName> {
+        self.include
+            .iter()
+            .filter_map(|domain| DnsName::parse(domain.trim_start_matches('.')).ok())
+            .filter(|domain| !domain.is_empty())
+    }
+
+    /// The filter as sent to external-dns during negotiation.
+    pub fn filters(&self) -> Filters {
+        Filters {
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            regex_include: self.regex_include.as_ref().map(|r| r.to_string()),
+            regex_exclude: self.regex_exclude.as_ref().map(|r| r.to_string()),
+        }
+    }
+}
+
+/// Whether `dns_name` falls within one of `domains`. A domain with a leading
+/// dot only matches its subdomains. An empty list matches according to
+/// `empty`.
+fn match_domains(domains: &[String], dns_name: &str, empty: bool) -> bool {
+    if domains.is_empty() {
+        return empty;
+    }
+
+    domains.iter().any(|domain| {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        if domain.is_empty() {
+            true
+        } else if domain.starts_with('.') {
+            dns_name.ends_with(&domain)
+        } else {
+            dns_name == domain || dns_name.ends_with(&format!(".{}", domain))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> DnsName {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn test_domain_lists() {
+        let filter = DomainFilter {
+            include: vec!["example.com".to_string(), ".example.org".to_string()],
+            exclude: vec!["internal.example.com.".to_string()],
+            ..Default::default()
+        };
+        assert!(filter.matches(&name("example.com")));
+        assert!(filter.matches(&name("API.example.com.")));
+        assert!(filter.matches(&name("api.example.org")));
+        assert!(!filter.matches(&name("example.org")));
+        assert!(!filter.matches(&name("badexample.com")));
+        assert!(!filter.matches(&name("db.internal.example.com")));
+    }
+
+    #[test]
+    fn test_regex() {
+        let filter = DomainFilter {
+            include: vec!["example.org".to_string()],
+            regex_include: Some(Regex::new(r"\.example\.com$").unwrap()),
+            regex_exclude: Some(Regex::new(r"^internal\.").unwrap()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&name("api.example.com")));
+        assert!(!filter.matches(&name("internal.example.com")));
+        assert!(!filter.matches(&name("api.example.org")));
+    }
+
+    #[test]
+    fn test_regex_exclude_only() {
+        let filter = DomainFilter {
+            include: vec!["example.org".to_string()],
+            regex_exclude: Some(Regex::new(r"^internal\.").unwrap()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&name("api.example.com")));
+        assert!(!filter.matches(&name("internal.example.com")));
+        assert!(!filter.matches(&name("internal.example.org")));
+    }
+
+    #[test]
+    fn test_split_longest_domain() {
+        let filter = DomainFilter {
+            include: vec![
+                "example.com".to_string(),
+                "staging.example.com.".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.split(&name("api.staging.example.com.")).unwrap(),
+            (name("api"), name("staging.example.com"))
+        );
+        assert_eq!(
+            filter.split(&name("db.prod.example.com")).unwrap(),
+            (name("db.prod"), name("example.com"))
+        );
+        assert!(filter.split(&name("api.example.org")).is_err());
+    }
+
+    #[test]
+    fn test_split_apex_and_wildcard() {
+        let filter = DomainFilter {
+            include: vec!["example.com".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.split(&name("example.com.")).unwrap(),
+            (name(""), name("example.com"))
+        );
+        assert_eq!(
+            filter.split(&name("*.apps.example.com")).unwrap(),
+            (name("*"), name("apps.example.com"))
+        );
+        assert_eq!(
+            filter.split(&name("*.example.com")).unwrap(),
+            (name("*"), name("example.com"))
+        );
+        assert!(filter.split(&name("*.example.org")).is_err());
+    }
+
+    #[test]
+    fn test_split_without_domains() {
+        let filter = DomainFilter::default();
+        let error = filter.split(&name("api.staging.example.com.")).unwrap_err();
+        assert!(error.to_string().contains("--domain"));
+        assert!(filter.split(&name("*.example.com")).is_err());
+    }
+
+    #[test]
+    fn test_empty_matches_everything() {
+        assert!(DomainFilter::default().matches(&name("anything.example.net")));
+    }
+}
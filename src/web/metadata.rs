@@ -0,0 +1,223 @@
+use crate::web::provider_specific::ProviderOptions;
+use opnsense::models::SearchRequest;
+use std::collections::BTreeMap;
+
+/// Every description written by this webhook starts with this prefix. Rows
+/// without it are never touched.
+pub const RECORD_DESCRIPTION_PREFIX: &str = "_ouw_";
+
+/// A search for the rows this webhook may own. OPNsense matches the phrase
+/// anywhere in any field, so the results still need `Metadata::parse`.
+pub fn owned_rows() -> SearchRequest {
+    SearchRequest::default().search_phrase(RECORD_DESCRIPTION_PREFIX)
+}
+
+pub const OWNER_LABEL: &str = "owner";
+pub const RESOURCE_LABEL: &str = "resource";
+
+const FIELD_SEPARATOR: char = ';';
+
+/// Separates the fields from the free text description set through
+/// `ProviderOptions`.
+const DESCRIPTION_SEPARATOR: char = '|';
+
+/// Key of the TTL external-dns requested for the record.
+const TTL_KEY: &str = "t";
+/// Keys of the `ProviderOptions` that differ from the defaults.
+const DISABLED_KEY: &str = "off";
+const MX_PRIORITY_KEY: &str = "mx";
+const REUSE_PARENT_KEY: &str = "reuse";
+
+/// Short keys for the labels external-dns sets on nearly every record.
+const SHORT_KEYS: [(&str, &str); 3] = [
+    ("o", OWNER_LABEL),
+    ("r", RESOURCE_LABEL),
+    ("w", "ownedRecord"),
+];
+
+/// external-dns labels stored in the description of a host override or alias.
+///
+/// The description is encoded as `_ouw_;o=<owner>;r=<resource>;<key>=<value>`,
+/// with the common labels shortened to a single letter, followed by `;t=<ttl>`
+/// when the record has a TTL and by any non-default `ProviderOptions`. A
+/// custom description goes last, after ` | `. A bare `_ouw_` marks a row owned
+/// by the webhook without any labels attached.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub labels: BTreeMap<String, String>,
+    pub ttl: Option<i64>,
+    pub options: ProviderOptions,
+}
+
+impl From<&BTreeMap<String, String>> for Metadata {
+    fn from(labels: &BTreeMap<String, String>) -> Self {
+        Metadata {
+            labels: labels.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Metadata {
+    /// Parse a row description, returning `None` for rows not owned by us.
+    pub fn parse(description: &str) -> Option<Self> {
+        let fields = description.strip_prefix(RECORD_DESCRIPTION_PREFIX)?;
+        let mut metadata = Metadata::default();
+        let fields = match fields.split_once(DESCRIPTION_SEPARATOR) {
+            Some((fields, description)) => {
+                metadata.options.description = Some(description.trim().to_string());
+                fields.trim_end()
+            }
+            None => fields,
+        };
+        for field in fields.split(FIELD_SEPARATOR) {
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            match key {
+                TTL_KEY => metadata.ttl = value.parse().ok(),
+                DISABLED_KEY => metadata.options.disabled = value == "1",
+                MX_PRIORITY_KEY => metadata.options.mx_priority = value.parse().ok(),
+                REUSE_PARENT_KEY => metadata.options.reuse_parent = value != "0",
+                _ => {
+                    let key = SHORT_KEYS
+                        .iter()
+                        .find(|(short, _)| *short == key)
+                        .map(|(_, label)| label.to_string())
+                        .unwrap_or_else(|| unescape(key));
+                    metadata.labels.insert(key, unescape(value));
+                }
+            }
+        }
+
+        Some(metadata)
+    }
+
+    pub fn to_description(&self) -> String {
+        let mut description = RECORD_DESCRIPTION_PREFIX.to_string();
+        for (key, value) in &self.labels {
+            let key = SHORT_KEYS
+                .iter()
+                .find(|(_, label)| label == key)
+                .map(|(short, _)| short.to_string())
+                .unwrap_or_else(|| escape(key));
+            description.push(FIELD_SEPARATOR);
+            description.push_str(&key);
+            description.push('=');
+            description.push_str(&escape(value));
+        }
+        let mut reserved = vec![];
+        if let Some(ttl) = self.ttl {
+            reserved.push((TTL_KEY, ttl.to_string()));
+        }
+        if self.options.disabled {
+            reserved.push((DISABLED_KEY, "1".to_string()));
+        }
+        if let Some(priority) = self.options.mx_priority {
+            reserved.push((MX_PRIORITY_KEY, priority.to_string()));
+        }
+        if !self.options.reuse_parent {
+            reserved.push((REUSE_PARENT_KEY, "0".to_string()));
+        }
+        for (key, value) in reserved {
+            description.push(FIELD_SEPARATOR);
+            description.push_str(&format!("{}={}", key, value));
+        }
+        if let Some(text) = &self.options.description {
+            description.push_str(&format!(" {} {}", DESCRIPTION_SEPARATOR, text));
+        }
+
+        description
+    }
+
+    /// Apply the labels and TTL of `other` on top of these. The options are
+    /// kept.
+    pub fn merge(&self, other: &Metadata) -> Metadata {
+        let mut merged = self.clone();
+        merged.labels.extend(other.labels.clone());
+        merged.ttl = other.ttl.or(self.ttl);
+        merged
+    }
+
+    /// Remove the labels set in `other`.
+    pub fn without(&self, other: &Metadata) -> Metadata {
+        let mut remaining = self.clone();
+        remaining
+            .labels
+            .retain(|key, _| !other.labels.contains_key(key));
+        remaining
+    }
+
+    pub fn owner(&self) -> Option<&String> {
+        self.labels.get(OWNER_LABEL)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace(FIELD_SEPARATOR, "%3B")
+        .replace('=', "%3D")
+        .replace(DESCRIPTION_SEPARATOR, "%7C")
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("%7C", "|")
+        .replace("%3D", "=")
+        .replace("%3B", ";")
+        .replace("%25", "%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bare_prefix() {
+        assert_eq!(Metadata::parse("_ouw_"), Some(Metadata::default()));
+        assert_eq!(Metadata::parse("manually added"), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let metadata = Metadata {
+            labels: BTreeMap::from([
+                (OWNER_LABEL.to_string(), "default;weird=owner%".to_string()),
+                (
+                    RESOURCE_LABEL.to_string(),
+                    "service/default/my-service".to_string(),
+                ),
+                ("team".to_string(), "dns".to_string()),
+            ]),
+            ttl: Some(300),
+            options: ProviderOptions::default(),
+        };
+        let description = metadata.to_description();
+        assert_eq!(
+            description,
+            "_ouw_;o=default%3Bweird%3Downer%25;r=service/default/my-service;team=dns;t=300"
+        );
+        assert_eq!(Metadata::parse(&description), Some(metadata));
+    }
+
+    #[test]
+    fn test_options_round_trip() {
+        let metadata = Metadata {
+            labels: BTreeMap::from([(OWNER_LABEL.to_string(), "a|b".to_string())]),
+            ttl: None,
+            options: ProviderOptions {
+                description: Some("Mail for team | dns".to_string()),
+                disabled: true,
+                mx_priority: Some(10),
+                reuse_parent: false,
+            },
+        };
+        let description = metadata.to_description();
+        assert_eq!(
+            description,
+            "_ouw_;o=a%7Cb;off=1;mx=10;reuse=0 | Mail for team | dns"
+        );
+        assert_eq!(Metadata::parse(&description), Some(metadata));
+    }
+}
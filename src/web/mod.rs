@@ -1,15 +1,45 @@
+mod apply;
+mod error;
+mod filter;
+mod metadata;
 mod models;
+mod pipeline;
+mod provider_specific;
+mod registry;
+mod snapshot;
+mod transaction;
+mod ttl;
 
+pub use crate::web::apply::{Applier, ApplyMode};
+pub use crate::web::error::{default_catcher, not_found, unprocessable_entity};
+pub use crate::web::filter::DomainFilter;
+pub use crate::web::pipeline::{Pipeline, flush_on_shutdown};
+pub use crate::web::registry::TxtRegistry;
+pub use crate::web::snapshot::SnapshotCache;
+pub use crate::web::transaction::MutationQueue;
+pub use crate::web::ttl::{DEFAULT_TTL, TtlPolicy};
+
+use crate::web::error::{FailedRecord, WebhookError};
+use crate::web::metadata::Metadata;
 use crate::web::models::RecordType;
+use crate::web::provider_specific::ProviderOptions;
+use crate::web::transaction::Transaction;
+use anyhow::{Context, Error, anyhow};
+use opnsense::ApiError;
+use opnsense::dns_name::DnsName;
+use opnsense::models::HostAliasRow;
+use opnsense::models::HostAliasUpdate;
+use opnsense::models::HostOverrideRow;
 use opnsense::models::HostOverrideType;
+use opnsense::models::HostOverrideUpdate;
+use opnsense::models::NewHostAlias;
 use opnsense::models::NewHostOverride;
+use opnsense::models::Uuid;
 use rocket::State;
 use rocket::http::Status;
 use rocket::response::Responder;
 use rocket::serde::json::Json;
 
-const RECORD_DESCRIPTION_PREFIX: &'static str = "_ouw_";
-
 #[derive(Responder)]
 #[response(
     status = 200,
@@ -22,95 +52,844 @@ pub fn healthz() -> &'static str {
     "OK"
 }
 
+#[get("/status")]
+pub fn status(pipeline: &State<Pipeline>) -> Json<apply::ApplierStatus> {
+    Json(pipeline.applier.status())
+}
+
 #[get("/")]
-pub fn negotiate(domains: &State<Vec<String>>) -> WebhookJson<models::Filters> {
-    let f = models::Filters::new(&domains);
-    WebhookJson(Json(f))
+pub fn negotiate(filter: &State<DomainFilter>) -> WebhookJson<models::Filters> {
+    WebhookJson(Json(filter.filters()))
 }
 
 #[get("/records")]
-pub async fn records_get(opnsense: &State<opnsense::Opnsense>) -> WebhookJson<Vec<models::Record>> {
-    //  Host Overrides <-> A records
+pub async fn records_get(
+    opnsense: &State<opnsense::Opnsense>,
+    registry: &State<TxtRegistry>,
+    filter: &State<DomainFilter>,
+    ttl: &State<TtlPolicy>,
+    pipeline: &State<Pipeline>,
+) -> Result<WebhookJson<Vec<models::Record>>, WebhookError> {
+    //  Host Overrides <-> A/AAAA/MX/TXT records
     //  Host Aliases   <-> CName records
-    let host_overrides = opnsense.unbound_get_host_overrides().await.unwrap();
-    let host_aliases = opnsense.unbound_get_host_aliases().await.unwrap();
+    //  Descriptions   <-> TXT registry records
+    let snapshot = pipeline.cache.get(opnsense).await?;
+    let host_overrides = &snapshot.host_overrides;
+    let host_aliases = &snapshot.host_aliases;
 
     let mut resp: Vec<models::Record> = vec![];
+    let mut owned: Vec<(models::Record, Metadata)> = vec![];
     for row in &host_overrides.rows {
-        if !row.enabled || !row.description.starts_with(RECORD_DESCRIPTION_PREFIX) {
+        let Some(metadata) = Metadata::parse(&row.description) else {
+            continue;
+        };
+        // Rows disabled by hand are gone as far as external-dns is concerned.
+        if !row.enabled && !metadata.options.disabled {
             continue;
         }
-        let record: models::Record = row.into();
-        resp.push(record);
+        match models::Record::try_from(row) {
+            Ok(record) if filter.matches(&record.dns_name) => owned.push((record, metadata)),
+            Ok(_) => {}
+            Err(e) => warn!("Skipping host override {}: {:#}", row.uuid, e),
+        }
     }
 
     for row in &host_aliases.rows {
-        if !row.enabled || !row.description.starts_with(RECORD_DESCRIPTION_PREFIX) {
+        let Some(metadata) = Metadata::parse(&row.description) else {
             continue;
+        };
+        // Rows disabled by hand are gone as far as external-dns is concerned.
+        if !row.enabled && !metadata.options.disabled {
+            continue;
+        }
+        match models::Record::try_from(row) {
+            Ok(record) if filter.matches(&record.dns_name) => owned.push((record, metadata)),
+            Ok(_) => {}
+            Err(e) => warn!("Skipping host alias {}: {:#}", row.uuid, e),
+        }
+    }
+
+    for (mut record, metadata) in group_records(owned) {
+        record.record_ttl = metadata.ttl.unwrap_or(ttl.default);
+        record.labels = metadata.labels.clone();
+        record.provider_specific = metadata.options.to_properties();
+        if let Some(txt) = registry.synthesize(&record, &metadata)
+            && !resp.iter().any(|i| i.dns_name == txt.dns_name)
+        {
+            resp.push(txt);
         }
-        let record: models::Record = row.into();
         resp.push(record);
     }
 
-    WebhookJson(Json(resp))
+    Ok(WebhookJson(Json(resp)))
 }
 
-fn dns_name_to_hostname_and_domain(dns_name: &str) -> Option<(String, String)> {
-    match dns_name.split_once(".") {
-        Some((first, rest)) => Some((first.to_string(), rest.to_string())),
-        None => None,
+/// Merge rows for the same name and type into a single record holding all of
+/// their targets, in a stable order so external-dns sees no spurious changes.
+fn group_records(rows: Vec<(models::Record, Metadata)>) -> Vec<(models::Record, Metadata)> {
+    let mut grouped: Vec<(models::Record, Metadata)> = vec![];
+    for (record, metadata) in rows {
+        let existing = grouped
+            .iter_mut()
+            .find(|(i, _)| i.dns_name == record.dns_name && i.record_type == record.record_type);
+        match existing {
+            Some((i, existing_metadata)) => {
+                for target in record.targets {
+                    if !i.targets.contains(&target) {
+                        i.targets.push(target);
+                    }
+                }
+                *existing_metadata = existing_metadata.merge(&metadata);
+            }
+            None => grouped.push((record, metadata)),
+        }
     }
+
+    for (record, _) in grouped.iter_mut() {
+        record.targets.sort();
+    }
+    grouped.sort_by(|(a, _), (b, _)| {
+        (&a.dns_name, &a.record_type).cmp(&(&b.dns_name, &b.record_type))
+    });
+    grouped
 }
 
-#[post("/records", format = "json", data = "<body>")]
-pub async fn records_post(
-    opnsense: &State<opnsense::Opnsense>,
-    body: Json<models::UpdateRecords>,
-) -> Status {
-    let records = body.into_inner();
-    // match validate_records(&records) {
-    //     Err(_) => return Status::InternalServerError,
-    //     Ok(_) => {}
-    // }
-
-    // let overrides = opnsense.unbound_get_host_overrides().await.unwrap();
-    // let aliases = opnsense.unbound_get_host_aliases().await.unwrap();
-
-    for i in &records.create {
-        match i.record_type {
-            RecordType::A => {
-                let (hostname, domain) = match dns_name_to_hostname_and_domain(&i.dns_name) {
-                    Some((hostname, domain)) => (hostname, domain),
-                    None => return Status::InternalServerError,
+/// Find the host override an alias for `target` should hang off of, if any.
+///
+/// Unbound host aliases cannot point at an arbitrary name, only at an existing
+/// host override, so `target` must already be served by an enabled one.
+/// Without `reuse_parent`, only overrides owned by the webhook are considered.
+async fn find_parent_override(
+    tx: &mut Transaction<'_>,
+    target: &DnsName,
+    reuse_parent: bool,
+) -> Result<Option<Uuid>, Error> {
+    let overrides = tx.opnsense().unbound_get_host_overrides().await?;
+    let parent = overrides
+        .rows
+        .iter()
+        .filter(|row| row.enabled)
+        .filter(|row| reuse_parent || Metadata::parse(&row.description).is_some())
+        .find(|row| models::fqdn(&row.hostname, &row.domain).is_ok_and(|i| &i == target))
+        .map(|row| row.uuid.clone());
+    Ok(parent)
+}
+
+async fn create_host_alias(
+    tx: &mut Transaction<'_>,
+    filter: &DomainFilter,
+    record: &models::Record,
+    metadata: &Metadata,
+) -> Result<(), Error> {
+    let (hostname, domain) = filter.split(&record.dns_name)?;
+    let target = match record.targets.as_slice() {
+        [target] => target,
+        _ => Err(anyhow!(
+            "CNAME {} must have exactly one target, got {:?}",
+            record.dns_name,
+            record.targets
+        ))?,
+    };
+
+    // Replays of a batch find the alias already in place.
+    let description = metadata.to_description();
+    let enabled = !metadata.options.disabled;
+    let host_aliases = tx.opnsense().unbound_get_host_aliases().await?;
+    let existing = host_aliases
+        .rows
+        .iter()
+        .filter(|row| {
+            owned_by(&row.description, metadata)
+                && models::fqdn(&row.hostname, &row.domain).is_ok_and(|i| i == record.dns_name)
+                && same_alias_target(target, row)
+        })
+        .max_by_key(|row| row.enabled);
+    match existing {
+        Some(row) if row.enabled == enabled && row.description == description => {
+            debug!("CNAME {} already exists", record.dns_name);
+            return Ok(());
+        }
+        Some(row) => {
+            let update = HostAliasUpdate {
+                enabled: Some(enabled),
+                host: None,
+                description: Some(description),
+            };
+            tx.set_host_alias(row, &update).await?;
+            return Ok(());
+        }
+        None => {}
+    }
+
+    // A CNAME to a name outside of Unbound, such as a load balancer's, has
+    // nothing to hang off of. Failing would roll back the rest of the batch,
+    // and external-dns would retry it forever, so only this record is skipped.
+    let target = DnsName::parse(target)?;
+    let reuse_parent = metadata.options.reuse_parent;
+    let Some(parent) = find_parent_override(tx, &target, reuse_parent).await? else {
+        warn!(
+            "Skipping CNAME {}, as {} has no enabled host override{} to attach it to",
+            record.dns_name,
+            target,
+            if reuse_parent {
+                ""
+            } else {
+                " owned by the webhook"
+            }
+        );
+        return Ok(());
+    };
+    let payload = NewHostAlias {
+        description,
+        domain,
+        enabled,
+        hostname,
+        host: parent,
+    };
+    tx.add_host_alias(&payload).await?;
+
+    Ok(())
+}
+
+/// Delete the owned host alias matching `record`, if any, returning the UUIDs
+/// of the deleted rows.
+async fn delete_host_alias(
+    tx: &mut Transaction<'_>,
+    record: &models::Record,
+    rows: &[HostAliasRow],
+) -> Result<Vec<Uuid>, Error> {
+    let matches = rows.iter().filter(|row| {
+        Metadata::parse(&row.description).is_some()
+            && models::fqdn(&row.hostname, &row.domain).is_ok_and(|i| i == record.dns_name)
+            && record
+                .targets
+                .iter()
+                .any(|target| same_alias_target(target, row))
+    });
+
+    let mut deleted = vec![];
+    for row in matches {
+        tx.del_host_alias(row).await?;
+        deleted.push(row.uuid.clone());
+    }
+    if deleted.is_empty() {
+        debug!("CNAME {} is already deleted", record.dns_name);
+    }
+
+    Ok(deleted)
+}
+
+/// Delete every owned host override matching `record` and one of its targets,
+/// adding the UUIDs of the deleted rows to `deleted`.
+///
+/// An alias serves the address of the one override it points at, so aliases
+/// still pointing at a row are first moved to a surviving override for the
+/// same name, such as the one an update just added. Only when none is left is
+/// the row kept, with a warning, rather than orphan the aliases.
+async fn delete_host_override(
+    tx: &mut Transaction<'_>,
+    record: &models::Record,
+    rows: &[HostOverrideRow],
+    remaining_aliases: &[HostAliasRow],
+    deleted: &mut Vec<Uuid>,
+) -> Result<(), Error> {
+    let dns_name = &record.dns_name;
+    let rr = host_override_type(&record.record_type)
+        .ok_or_else(|| anyhow!("{:?} is not a host override type", record.record_type))?;
+    let same_name = |row: &HostOverrideRow| {
+        models::fqdn(&row.hostname, &row.domain).is_ok_and(|i| &i == dns_name)
+    };
+    let matches: Vec<&HostOverrideRow> = rows
+        .iter()
+        .filter(|row| {
+            Metadata::parse(&row.description).is_some()
+                && same_name(row)
+                && row.rr == rr
+                && record
+                    .targets
+                    .iter()
+                    .any(|target| same_target(&rr, target, row))
+        })
+        .collect();
+    if matches.is_empty() {
+        debug!(
+            "{:?} {} is already deleted",
+            record.record_type, record.dns_name
+        );
+        return Ok(());
+    }
+
+    // The search only names the parent of an alias, so ask for the UUID.
+    let mut children: Vec<(&HostAliasRow, Uuid)> = vec![];
+    for alias in remaining_aliases
+        .iter()
+        .filter(|alias| *dns_name == models::host_alias_target(alias).as_str())
+    {
+        let parent = match tx
+            .opnsense()
+            .unbound_get_host_alias(alias.uuid.clone())
+            .await
+        {
+            Ok(item) => item.host,
+            Err(e) if matches!(e.downcast_ref::<ApiError>(), Some(ApiError::NotFound(_))) => None,
+            Err(e) => Err(e)?,
+        };
+        if let Some(parent) = parent
+            && matches.iter().any(|row| row.uuid == parent)
+        {
+            children.push((alias, parent));
+        }
+    }
+    let survivor = rows
+        .iter()
+        .filter(|row| {
+            row.enabled
+                && same_name(row)
+                && !deleted.contains(&row.uuid)
+                && !matches.iter().any(|i| i.uuid == row.uuid)
+        })
+        .max_by_key(|row| row.rr == rr);
+
+    for row in matches {
+        let orphans: Vec<&HostAliasRow> = children
+            .iter()
+            .filter(|(_, parent)| *parent == row.uuid)
+            .map(|(alias, _)| *alias)
+            .collect();
+        if !orphans.is_empty() {
+            let Some(survivor) = survivor else {
+                warn!(
+                    "Keeping {:?} {} {}, the last host override host aliases {:?} point at",
+                    record.record_type,
+                    dns_name,
+                    models::host_override_target(row),
+                    orphans
+                        .iter()
+                        .map(|alias| format!("{}.{}", alias.hostname, alias.domain))
+                        .collect::<Vec<_>>()
+                );
+                continue;
+            };
+            for alias in orphans {
+                tx.set_host_alias_parent(alias, &row.uuid, &survivor.uuid)
+                    .await?;
+            }
+        }
+        tx.del_host_override(row).await?;
+        deleted.push(row.uuid.clone());
+    }
+
+    Ok(())
+}
+
+async fn delete_records(
+    tx: &mut Transaction<'_>,
+    registry: &TxtRegistry,
+    ttl: &TtlPolicy,
+    records: &[models::Record],
+) -> Result<(), Error> {
+    let (registry_records, records): (Vec<_>, Vec<_>) = records
+        .iter()
+        .cloned()
+        .partition(|i| registry.is_registry_record(i));
+    if !records.is_empty() {
+        delete_host_entries(tx, &records).await?;
+    }
+    // Whatever the registry records covered is usually gone by now, but drop
+    // the ownership of anything external-dns left behind.
+    if !registry_records.is_empty() {
+        set_ownership(tx, registry, ttl, &registry_records, true).await?;
+    }
+
+    Ok(())
+}
+
+async fn delete_host_entries(
+    tx: &mut Transaction<'_>,
+    records: &[models::Record],
+) -> Result<(), Error> {
+    let host_overrides = tx.opnsense().unbound_get_host_overrides().await?;
+    let host_aliases = tx.opnsense().unbound_get_host_aliases().await?;
+
+    // Aliases reference their parent override, so they must go first.
+    let (aliases, overrides): (Vec<_>, Vec<_>) = records
+        .iter()
+        .partition(|i| i.record_type == RecordType::CNAME);
+    let mut deleted = vec![];
+    for i in &aliases {
+        let uuids = delete_host_alias(tx, i, &host_aliases.rows)
+            .await
+            .context(FailedRecord::new("delete", i))?;
+        deleted.extend(uuids);
+    }
+
+    let remaining_aliases: Vec<HostAliasRow> = host_aliases
+        .rows
+        .into_iter()
+        .filter(|row| !deleted.contains(&row.uuid))
+        .collect();
+    for i in &overrides {
+        delete_host_override(
+            tx,
+            i,
+            &host_overrides.rows,
+            &remaining_aliases,
+            &mut deleted,
+        )
+        .await
+        .context(FailedRecord::new("delete", i))?;
+    }
+
+    Ok(())
+}
+
+/// Whether `target` from external-dns refers to the value stored in `row`.
+fn same_target(rr: &HostOverrideType, target: &str, row: &HostOverrideRow) -> bool {
+    match rr {
+        HostOverrideType::MX => match models::parse_mx_target(target, None) {
+            Ok((priority, exchange)) => {
+                row.mxprio == priority.to_string()
+                    && DnsName::parse(&row.mx).is_ok_and(|mx| mx == exchange.as_str())
+            }
+            Err(_) => false,
+        },
+        HostOverrideType::TXT => row.txtdata == models::unquote_txt_target(target),
+        _ => row.server == target,
+    }
+}
+
+/// Whether the CNAME `target` from external-dns is the parent of `row`.
+fn same_alias_target(target: &str, row: &HostAliasRow) -> bool {
+    DnsName::parse(target).is_ok_and(|target| target == models::host_alias_target(row).as_str())
+}
+
+/// Whether a row with `description` is owned by us for the same owner as
+/// `metadata`.
+fn owned_by(description: &str, metadata: &Metadata) -> bool {
+    Metadata::parse(description).is_some_and(|current| current.owner() == metadata.owner())
+}
+
+fn host_override_type(record_type: &RecordType) -> Option<HostOverrideType> {
+    match record_type {
+        RecordType::A => Some(HostOverrideType::A),
+        RecordType::AAAA => Some(HostOverrideType::AAAA),
+        RecordType::MX => Some(HostOverrideType::MX),
+        RecordType::TXT => Some(HostOverrideType::TXT),
+        RecordType::CNAME | RecordType::Unsupported(_) => None,
+    }
+}
+
+async fn create_host_override(
+    tx: &mut Transaction<'_>,
+    filter: &DomainFilter,
+    ttl: &TtlPolicy,
+    record: &models::Record,
+    metadata: &Metadata,
+) -> Result<(), Error> {
+    let (hostname, domain) = filter.split(&record.dns_name)?;
+    let rr = host_override_type(&record.record_type)
+        .ok_or_else(|| anyhow!("{:?} is not a host override type", record.record_type))?;
+
+    // Replays of a batch find some or all of the targets already in place.
+    let description = metadata.to_description();
+    let enabled = !metadata.options.disabled;
+    let host_override_ttl = ttl.host_override_ttl(metadata.ttl);
+    let host_overrides = tx.opnsense().unbound_get_host_overrides().await?;
+    for target in &record.targets {
+        let target = &match rr {
+            HostOverrideType::MX => {
+                let (priority, exchange) =
+                    models::parse_mx_target(target, metadata.options.mx_priority)?;
+                format!("{} {}", priority, exchange)
+            }
+            _ => target.clone(),
+        };
+        let existing = host_overrides
+            .rows
+            .iter()
+            .filter(|row| {
+                owned_by(&row.description, metadata)
+                    && models::fqdn(&row.hostname, &row.domain).is_ok_and(|i| i == record.dns_name)
+                    && row.rr == rr
+                    && same_target(&rr, target, row)
+            })
+            .max_by_key(|row| row.enabled);
+        match existing {
+            Some(row)
+                if row.enabled == enabled
+                    && row.description == description
+                    && (!ttl.host_overrides || row.ttl == host_override_ttl) =>
+            {
+                debug!(
+                    "{:?} {} {} already exists",
+                    record.record_type, record.dns_name, target
+                );
+                continue;
+            }
+            Some(row) => {
+                let update = HostOverrideUpdate {
+                    enabled: Some(enabled),
+                    ttl: ttl.host_overrides.then(|| host_override_ttl.clone()),
+                    description: Some(description.clone()),
                 };
-                for server in &i.targets {
-                    let payload = NewHostOverride {
-                        enabled: true,
-                        hostname: hostname.clone(),
-                        domain: domain.clone(),
-                        rr: HostOverrideType::A,
-                        mxprio: "".to_string(),
-                        mx: "".to_string(),
-                        server: server.to_string(),
-                        description: "_ouw_".to_string(),
-                    };
-                    opnsense.unbound_add_host_override(&payload).await.unwrap();
-                }
+                tx.set_host_override(row, &update).await?;
+                continue;
             }
-            RecordType::CNAME => {}
+            None => {}
         }
+
+        let mut payload = NewHostOverride {
+            enabled,
+            hostname: hostname.clone(),
+            domain: domain.clone(),
+            rr: rr.clone(),
+            mxprio: "".to_string(),
+            mx: "".to_string(),
+            server: "".to_string(),
+            txtdata: "".to_string(),
+            ttl: host_override_ttl.clone(),
+            description: description.clone(),
+        };
+        match rr {
+            HostOverrideType::MX => {
+                let (priority, exchange) = models::parse_mx_target(target, None)?;
+                payload.mxprio = priority.to_string();
+                payload.mx = exchange;
+            }
+            HostOverrideType::TXT => payload.txtdata = models::unquote_txt_target(target),
+            _ => payload.server = target.to_string(),
+        }
+        tx.add_host_override(&payload).await?;
     }
-    for i in &records.update_old {
-        info!("Update Old: {:?}", i)
+
+    Ok(())
+}
+
+/// Rewrite the labels stored on every owned row for which `relabel` returns
+/// new metadata. `relabel` is given the current metadata, name and type of
+/// each row.
+async fn relabel_rows<F>(tx: &mut Transaction<'_>, ttl: &TtlPolicy, relabel: F) -> Result<(), Error>
+where
+    F: Fn(&Metadata, &DnsName, &RecordType) -> Option<Metadata>,
+{
+    let host_overrides = tx
+        .opnsense()
+        .unbound_search_all_host_overrides(&metadata::owned_rows())
+        .await?;
+    for row in &host_overrides.rows {
+        let (Some(current), Ok(record_type)) = (
+            Metadata::parse(&row.description),
+            RecordType::try_from(&row.rr),
+        ) else {
+            continue;
+        };
+        let Ok(dns_name) = models::fqdn(&row.hostname, &row.domain) else {
+            continue;
+        };
+        match relabel(&current, &dns_name, &record_type) {
+            Some(metadata) if metadata != current => {
+                let update = HostOverrideUpdate {
+                    enabled: enabled_update(&current, &metadata),
+                    ttl: ttl
+                        .host_overrides
+                        .then(|| ttl.host_override_ttl(metadata.ttl)),
+                    description: Some(metadata.to_description()),
+                };
+                tx.set_host_override(row, &update).await?;
+            }
+            _ => {}
+        }
     }
-    for i in &records.update_new {
-        info!("Update New: {:?}", i)
+
+    let host_aliases = tx
+        .opnsense()
+        .unbound_search_all_host_aliases(&metadata::owned_rows())
+        .await?;
+    for row in &host_aliases.rows {
+        let Some(current) = Metadata::parse(&row.description) else {
+            continue;
+        };
+        let Ok(dns_name) = models::fqdn(&row.hostname, &row.domain) else {
+            continue;
+        };
+        match relabel(&current, &dns_name, &RecordType::CNAME) {
+            Some(metadata) if metadata != current => {
+                let update = HostAliasUpdate {
+                    enabled: enabled_update(&current, &metadata),
+                    host: None,
+                    description: Some(metadata.to_description()),
+                };
+                tx.set_host_alias(row, &update).await?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// The `enabled` update for a row whose metadata changes from `current` to
+/// `metadata`, if the disabled option changed.
+fn enabled_update(current: &Metadata, metadata: &Metadata) -> Option<bool> {
+    (current.options.disabled != metadata.options.disabled).then_some(!metadata.options.disabled)
+}
+
+/// Rewrite the ownership stored on every owned row covered by one of
+/// `registry_records`. With `clear`, the ownership is removed instead.
+async fn set_ownership(
+    tx: &mut Transaction<'_>,
+    registry: &TxtRegistry,
+    ttl: &TtlPolicy,
+    registry_records: &[models::Record],
+    clear: bool,
+) -> Result<(), Error> {
+    relabel_rows(tx, ttl, |current, dns_name, record_type| {
+        registry_records
+            .iter()
+            .find(|i| registry.covers(i, dns_name, record_type))
+            .map(|i| match clear {
+                true => current.without(&TxtRegistry::metadata(i)),
+                false => current.merge(&TxtRegistry::metadata(i)),
+            })
+    })
+    .await
+}
+
+async fn create_records(
+    tx: &mut Transaction<'_>,
+    registry: &TxtRegistry,
+    filter: &DomainFilter,
+    ttl: &TtlPolicy,
+    records: &[models::Record],
+) -> Result<(), Error> {
+    let (registry_records, records): (Vec<_>, Vec<_>) = records
+        .iter()
+        .cloned()
+        .partition(|i| registry.is_registry_record(i));
+
+    // Aliases need their parent override to exist, so create those first.
+    let (aliases, overrides): (Vec<_>, Vec<_>) = records
+        .iter()
+        .partition(|i| i.record_type == RecordType::CNAME);
+    let mut covered = vec![false; registry_records.len()];
+    for i in overrides.into_iter().chain(aliases) {
+        // Fold the ownership from this batch's registry records into the new rows.
+        let owner = registry_records
+            .iter()
+            .position(|txt| registry.covers(txt, &i.dns_name, &i.record_type));
+        let mut metadata = Metadata::from(&i.labels);
+        metadata.ttl = Some(ttl.resolve(i.record_ttl));
+        metadata.options =
+            ProviderOptions::parse(&i.provider_specific).context(FailedRecord::new("create", i))?;
+        if let Some(index) = owner {
+            covered[index] = true;
+            metadata = metadata.merge(&TxtRegistry::metadata(&registry_records[index]));
+        }
+
+        match i.record_type {
+            RecordType::A | RecordType::AAAA | RecordType::MX | RecordType::TXT => {
+                create_host_override(tx, filter, ttl, i, &metadata).await
+            }
+            RecordType::CNAME => create_host_alias(tx, filter, i, &metadata).await,
+            RecordType::Unsupported(_) => Err(anyhow!("Unsupported record type")),
+        }
+        .context(FailedRecord::new("create", i))?;
     }
-    for i in &records.delete {
-        info!("Delete {:?}", i)
+
+    // Registry records for rows that already exist only update their ownership.
+    let uncovered: Vec<models::Record> = registry_records
+        .into_iter()
+        .zip(covered)
+        .filter(|(_, covered)| !covered)
+        .map(|(txt, _)| txt)
+        .collect();
+    if !uncovered.is_empty() {
+        set_ownership(tx, registry, ttl, &uncovered, false)
+            .await
+            .context("Failed to update record ownership")?;
     }
 
-    Status::NoContent
+    Ok(())
+}
+
+/// Whether the rows kept by an update need new metadata, i.e. the labels, TTL
+/// or provider options of `new` differ from those of its `UpdateOld` record.
+fn needs_relabel(
+    old: Option<&models::Record>,
+    new: &models::Record,
+    options: &ProviderOptions,
+) -> bool {
+    let Some(old) = old else {
+        return true;
+    };
+    old.labels != new.labels
+        || old.record_ttl != new.record_ttl
+        || ProviderOptions::parse(&old.provider_specific).ok().as_ref() != Some(options)
+}
+
+/// Apply the target differences between paired `UpdateOld` and `UpdateNew`
+/// endpoints.
+///
+/// New targets are added before stale ones are removed so the name keeps
+/// resolving throughout the update.
+async fn update_records(
+    tx: &mut Transaction<'_>,
+    registry: &TxtRegistry,
+    filter: &DomainFilter,
+    ttl: &TtlPolicy,
+    old: &[models::Record],
+    new: &[models::Record],
+) -> Result<(), Error> {
+    let mut additions: Vec<models::Record> = vec![];
+    let mut removals: Vec<models::Record> = vec![];
+    let mut ownership: Vec<models::Record> = vec![];
+    let mut relabeled: Vec<(&models::Record, ProviderOptions)> = vec![];
+
+    for new_record in new {
+        if registry.is_registry_record(new_record) {
+            ownership.push(new_record.clone());
+            continue;
+        }
+        let options = ProviderOptions::parse(&new_record.provider_specific)
+            .context(FailedRecord::new("update", new_record))?;
+        let old_record = old
+            .iter()
+            .find(|i| i.dns_name == new_record.dns_name && i.record_type == new_record.record_type);
+        if needs_relabel(old_record, new_record, &options) {
+            relabeled.push((new_record, options));
+        }
+        let old_targets = old_record.map(|i| i.targets.as_slice()).unwrap_or_default();
+
+        let added: Vec<String> = new_record
+            .targets
+            .iter()
+            .filter(|target| !old_targets.contains(target))
+            .cloned()
+            .collect();
+        if !added.is_empty() {
+            additions.push(models::Record {
+                targets: added,
+                ..new_record.clone()
+            });
+        }
+
+        if let Some(old_record) = old_record {
+            let removed: Vec<String> = old_record
+                .targets
+                .iter()
+                .filter(|target| !new_record.targets.contains(target))
+                .cloned()
+                .collect();
+            if !removed.is_empty() {
+                removals.push(models::Record {
+                    targets: removed,
+                    ..old_record.clone()
+                });
+            }
+        }
+    }
+
+    for old_record in old {
+        if registry.is_registry_record(old_record) {
+            continue;
+        }
+        let paired = new
+            .iter()
+            .any(|i| i.dns_name == old_record.dns_name && i.record_type == old_record.record_type);
+        if !paired {
+            warn!(
+                "Ignoring update of {:?} {} without a new endpoint",
+                old_record.record_type, old_record.dns_name
+            );
+        }
+    }
+
+    create_records(tx, registry, filter, ttl, &additions).await?;
+    delete_records(tx, registry, ttl, &removals).await?;
+    if !relabeled.is_empty() {
+        relabel_rows(tx, ttl, |current, dns_name, record_type| {
+            relabeled
+                .iter()
+                .find(|(i, _)| &i.dns_name == dns_name && &i.record_type == record_type)
+                .map(|(i, options)| {
+                    let mut metadata = current.merge(&(&i.labels).into());
+                    metadata.options = options.clone();
+                    if i.record_ttl > 0 {
+                        metadata.ttl = Some(i.record_ttl);
+                    }
+                    metadata
+                })
+        })
+        .await?;
+    }
+    if !ownership.is_empty() {
+        set_ownership(tx, registry, ttl, &ownership, false).await?;
+    }
+
+    Ok(())
+}
+
+async fn apply_changes(
+    tx: &mut Transaction<'_>,
+    registry: &TxtRegistry,
+    filter: &DomainFilter,
+    ttl: &TtlPolicy,
+    records: &models::UpdateRecords,
+) -> Result<(), Error> {
+    create_records(tx, registry, filter, ttl, &records.create).await?;
+    update_records(
+        tx,
+        registry,
+        filter,
+        ttl,
+        &records.update_old,
+        &records.update_new,
+    )
+    .await
+    .context("Failed to update records")?;
+    delete_records(tx, registry, ttl, &records.delete)
+        .await
+        .context("Failed to delete records")?;
+
+    Ok(())
+}
+
+// No `format = "json"`: external-dns sends
+// `application/external.dns.webhook+json;version=1`, which it would reject.
+#[post("/records", data = "<body>")]
+pub async fn records_post(
+    opnsense: &State<opnsense::Opnsense>,
+    registry: &State<TxtRegistry>,
+    filter: &State<DomainFilter>,
+    ttl: &State<TtlPolicy>,
+    pipeline: &State<Pipeline>,
+    body: Json<models::UpdateRecords<String>>,
+) -> Result<Status, WebhookError> {
+    let mut records = body.into_inner().validate();
+    filter.retain_changes(&mut records);
+
+    // Apply the batch as a unit, so a retry by external-dns starts from where
+    // this request did rather than on top of a partial result. Overlapping
+    // batches queue up and each plans against the rows as the previous one
+    // left them.
+    let mut tx = pipeline.queue.begin(opnsense).await;
+    if let Err(e) = apply_changes(&mut tx, registry, filter, ttl, &records).await {
+        warn!("Rolling back the batch: {:#}", e);
+        // Rolling back recreates deleted rows under new UUIDs, so the cached
+        // listing is stale either way.
+        let changed = tx.len() > 0;
+        tx.rollback().await;
+        if changed {
+            pipeline.cache.invalidate();
+        }
+        return Err(e.into());
+    }
+    if tx.len() > 0 {
+        pipeline.cache.invalidate();
+    }
+    pipeline.applier.mark_dirty(tx.len());
+
+    Ok(Status::NoContent)
     // Sample Request
     // curl -X POST http://localhost:8000/records \
     //   -H "Content-Type: application/json" \
@@ -133,9 +912,872 @@ pub async fn records_post(
     //   }'
 }
 
-#[post("/adjustendpoints", format = "json", data = "<body>")]
-pub fn adjust_endpoints(body: Json<Vec<models::Record>>) -> WebhookJson<Vec<models::Record>> {
-    // Assume all transactions are valid.
-    // TODO: Properly validate records.
-    WebhookJson(body)
+#[post("/adjustendpoints", data = "<body>")]
+pub fn adjust_endpoints(
+    filter: &State<DomainFilter>,
+    ttl: &State<TtlPolicy>,
+    body: Json<Vec<models::Endpoint>>,
+) -> WebhookJson<Vec<models::Record>> {
+    // Drop what OPNsense cannot store and report the rest the way records_get
+    // will, so external-dns plans against what it will actually read back.
+    let mut records = models::validate(body.into_inner());
+    filter.retain(&mut records);
+    let adjusted = records
+        .into_iter()
+        .filter_map(|record| {
+            let dns_name = record.dns_name.clone();
+            let normalized = record.normalize(ttl.default).and_then(|record| {
+                filter.split(&record.dns_name)?;
+                Ok(record)
+            });
+            match normalized {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    warn!("Dropping endpoint {}: {:#}", dns_name, e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    WebhookJson(Json(adjusted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Mock;
+    use rocket::http::ContentType;
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    fn record(dns_name: &str, record_type: RecordType, target: &str) -> models::Record {
+        models::Record {
+            dns_name: dns_name.parse().unwrap(),
+            targets: vec![target.to_string()],
+            record_type,
+            set_identifier: "".to_string(),
+            record_ttl: ttl::DEFAULT_TTL,
+            labels: BTreeMap::new(),
+            provider_specific: vec![],
+        }
+    }
+
+    #[test]
+    fn test_group_records() {
+        let rows = vec![
+            (
+                record("b.example.com", RecordType::A, "10.0.0.2"),
+                Metadata::default(),
+            ),
+            (
+                record("a.example.com", RecordType::AAAA, "fd00::1"),
+                Metadata::default(),
+            ),
+            (
+                record("b.example.com", RecordType::A, "10.0.0.1"),
+                Metadata::default(),
+            ),
+            (
+                record("a.example.com", RecordType::A, "10.0.0.3"),
+                Metadata::default(),
+            ),
+        ];
+
+        let grouped: Vec<(String, RecordType, Vec<String>)> = group_records(rows)
+            .into_iter()
+            .map(|(i, _)| (i.dns_name.to_string(), i.record_type, i.targets))
+            .collect();
+        assert_eq!(
+            grouped,
+            vec![
+                (
+                    "a.example.com".to_string(),
+                    RecordType::A,
+                    vec!["10.0.0.3".to_string()]
+                ),
+                (
+                    "a.example.com".to_string(),
+                    RecordType::AAAA,
+                    vec!["fd00::1".to_string()]
+                ),
+                (
+                    "b.example.com".to_string(),
+                    RecordType::A,
+                    vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_needs_relabel() {
+        let old = record("api.example.com", RecordType::A, "10.0.0.1");
+        let check = |new: &models::Record| {
+            let options = ProviderOptions::parse(&new.provider_specific).unwrap();
+            needs_relabel(Some(&old), new, &options)
+        };
+
+        // Only a target changed, so the kept rows stay as they are.
+        assert!(!check(&record(
+            "api.example.com",
+            RecordType::A,
+            "10.0.0.2"
+        )));
+
+        let mut labels = old.clone();
+        labels
+            .labels
+            .insert("resource".to_string(), "service/default/api".to_string());
+        assert!(check(&labels));
+        let ttl = models::Record {
+            record_ttl: 3600,
+            ..old.clone()
+        };
+        assert!(check(&ttl));
+        let options = models::Record {
+            provider_specific: vec![models::ProviderSpecificProperty {
+                name: provider_specific::DESCRIPTION.to_string(),
+                value: "API".to_string(),
+            }],
+            ..old.clone()
+        };
+        assert!(check(&options));
+
+        let options = ProviderOptions::parse(&[]).unwrap();
+        assert!(needs_relabel(None, &old, &options));
+    }
+
+    /// Create `record` against an empty table, expecting a single host
+    /// override matching `expected`.
+    async fn assert_creates(record: &models::Record, expected: &str) {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = opnsense::Opnsense::new(&server.url(), None, None, true).unwrap();
+        mock_search(&mut server, &[], &[]).await;
+        let add = server
+            .mock("POST", "/api/unbound/settings/addHostOverride/")
+            .match_body(mockito::Matcher::PartialJsonString(expected.to_string()))
+            .with_body(r#"{"result": "saved", "uuid": "new-uuid"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let filter = DomainFilter {
+            include: vec!["example.com".to_string()],
+            ..Default::default()
+        };
+        let metadata = Metadata::parse("_ouw_;o=default").unwrap();
+        let queue = MutationQueue::default();
+        let mut tx = queue.begin(&opnsense).await;
+        create_host_override(&mut tx, &filter, &TtlPolicy::default(), record, &metadata)
+            .await
+            .unwrap();
+        add.assert_async().await;
+    }
+
+    #[rocket::async_test]
+    async fn test_create_host_override_aaaa() {
+        assert_creates(
+            &record("api.example.com", RecordType::AAAA, "fd00::1"),
+            r#"{"host": {"hostname": "api", "domain": "example.com", "rr": "AAAA",
+                "server": "fd00::1"}}"#,
+        )
+        .await;
+    }
+
+    #[rocket::async_test]
+    async fn test_create_host_override_mx() {
+        assert_creates(
+            &record("example.com", RecordType::MX, "10 mail.example.com"),
+            r#"{"host": {"hostname": "", "domain": "example.com", "rr": "MX", "mxprio": "10",
+                "mx": "mail.example.com", "server": ""}}"#,
+        )
+        .await;
+    }
+
+    #[rocket::async_test]
+    async fn test_create_host_override_txt() {
+        assert_creates(
+            &record("api.example.com", RecordType::TXT, "\"v=spf1 -all\""),
+            r#"{"host": {"hostname": "api", "rr": "TXT", "txtdata": "v=spf1 -all"}}"#,
+        )
+        .await;
+    }
+
+    #[rocket::async_test]
+    async fn test_create_host_override_replay() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = opnsense::Opnsense::new(&server.url(), None, None, true).unwrap();
+        let row = |uuid: &str, enabled: &str, target: &str| {
+            format!(
+                r#"{{"uuid": "{}", "enabled": "{}", "hostname": "api", "domain": "example.com",
+                    "rr": "A (IPv4 address)", "mxprio": "", "mx": "", "server": "{}",
+                    "description": "_ouw_;o=default"}}"#,
+                uuid, enabled, target
+            )
+        };
+        let rows = format!(
+            r#"{{"rows": [{}, {}], "rowCount": 2, "total": 2, "current": 1}}"#,
+            row("enabled-uuid", "1", "10.0.0.1"),
+            row("disabled-uuid", "0", "10.0.0.2"),
+        );
+
+        let search = server
+            .mock("POST", "/api/unbound/settings/searchHostOverride/")
+            .with_body(rows)
+            .create_async()
+            .await;
+        let enable = server
+            .mock(
+                "POST",
+                "/api/unbound/settings/setHostOverride/disabled-uuid",
+            )
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"host": {"enabled": "1"}}"#.to_string(),
+            ))
+            .with_body(r#"{"result": "saved"}"#)
+            .create_async()
+            .await;
+        let add = server
+            .mock("POST", "/api/unbound/settings/addHostOverride/")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"host": {"server": "10.0.0.3"}}"#.to_string(),
+            ))
+            .with_body(r#"{"result": "saved", "uuid": "new-uuid"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut record = record("api.example.com", RecordType::A, "10.0.0.1");
+        record
+            .targets
+            .extend(["10.0.0.2".to_string(), "10.0.0.3".to_string()]);
+        let metadata = Metadata::parse("_ouw_;o=default").unwrap();
+        let filter = DomainFilter {
+            include: vec!["example.com".to_string()],
+            ..Default::default()
+        };
+        let queue = MutationQueue::default();
+        let mut tx = queue.begin(&opnsense).await;
+        create_host_override(&mut tx, &filter, &TtlPolicy::default(), &record, &metadata)
+            .await
+            .unwrap();
+
+        search.assert_async().await;
+        enable.assert_async().await;
+        add.assert_async().await;
+    }
+
+    #[rocket::async_test]
+    async fn test_create_host_alias_parent() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = opnsense::Opnsense::new(&server.url(), None, None, true).unwrap();
+        let row = |uuid: &str, enabled: &str| {
+            format!(
+                r#"{{"uuid": "{}", "enabled": "{}", "hostname": "api", "domain": "example.com",
+                    "rr": "A (IPv4 address)", "mxprio": "", "mx": "", "server": "10.0.0.1",
+                    "description": "Added by hand"}}"#,
+                uuid, enabled
+            )
+        };
+        server
+            .mock("POST", "/api/unbound/settings/searchHostOverride/")
+            .with_body(format!(
+                r#"{{"rows": [{}, {}], "rowCount": 2, "total": 2, "current": 1}}"#,
+                row("disabled-uuid", "0"),
+                row("manual-uuid", "1"),
+            ))
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/unbound/settings/searchHostAlias/")
+            .with_body(r#"{"rows": [], "rowCount": 0, "total": 0, "current": 1}"#)
+            .create_async()
+            .await;
+        let add = server
+            .mock("POST", "/api/unbound/settings/addHostAlias/")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"alias": {"hostname": "www", "host": "manual-uuid"}}"#.to_string(),
+            ))
+            .with_body(r#"{"result": "saved", "uuid": "alias-uuid"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let filter = DomainFilter {
+            include: vec!["example.com".to_string()],
+            ..Default::default()
+        };
+        let queue = MutationQueue::default();
+        let mut tx = queue.begin(&opnsense).await;
+
+        // The enabled override for the target is reused, the disabled one never.
+        let record = record("www.example.com", RecordType::CNAME, "api.example.com");
+        let mut metadata = Metadata::parse("_ouw_;o=default").unwrap();
+        create_host_alias(&mut tx, &filter, &record, &metadata)
+            .await
+            .unwrap();
+        add.assert_async().await;
+
+        // Nothing is made up for a target without an override, the CNAME is
+        // skipped.
+        let missing = models::Record {
+            targets: vec!["lb.example.net".to_string()],
+            ..record.clone()
+        };
+        create_host_alias(&mut tx, &filter, &missing, &metadata)
+            .await
+            .unwrap();
+
+        // Without reusing parents, the override made by hand does not count.
+        metadata.options.reuse_parent = false;
+        create_host_alias(&mut tx, &filter, &record, &metadata)
+            .await
+            .unwrap();
+        assert_eq!(tx.len(), 1);
+    }
+
+    fn override_row(uuid: &str, hostname: &str, rr: &str, target: &str, owned: bool) -> String {
+        let (server, mxprio, mx) = match target.split_once(' ') {
+            Some((mxprio, mx)) => ("", mxprio, mx),
+            None => (target, "", ""),
+        };
+        format!(
+            r#"{{"uuid": "{}", "enabled": "1", "hostname": "{}", "domain": "example.com",
+                "rr": "{}", "mxprio": "{}", "mx": "{}", "server": "{}", "description": "{}"}}"#,
+            uuid,
+            hostname,
+            rr,
+            mxprio,
+            mx,
+            server,
+            if owned {
+                "_ouw_;o=default"
+            } else {
+                "Added by hand"
+            }
+        )
+    }
+
+    fn alias_row(uuid: &str, hostname: &str, host: &str, owned: bool) -> String {
+        format!(
+            r#"{{"uuid": "{}", "enabled": "1", "host": "{}", "hostname": "{}",
+                "domain": "example.com", "description": "{}"}}"#,
+            uuid,
+            host,
+            hostname,
+            if owned {
+                "_ouw_;o=default"
+            } else {
+                "Added by hand"
+            }
+        )
+    }
+
+    fn search_body(rows: &[String]) -> String {
+        format!(
+            r#"{{"rows": [{}], "rowCount": {}, "total": {}, "current": 1}}"#,
+            rows.join(", "),
+            rows.len(),
+            rows.len()
+        )
+    }
+
+    async fn mock_search(server: &mut mockito::Server, overrides: &[String], aliases: &[String]) {
+        server
+            .mock("POST", "/api/unbound/settings/searchHostOverride/")
+            .with_body(search_body(overrides))
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/unbound/settings/searchHostAlias/")
+            .with_body(search_body(aliases))
+            .create_async()
+            .await;
+    }
+
+    async fn mock_alias_parent(server: &mut mockito::Server, uuid: &str, parent: &str) {
+        server
+            .mock(
+                "GET",
+                format!("/api/unbound/settings/getHostAlias/{}", uuid).as_str(),
+            )
+            .with_body(format!(
+                r#"{{"alias": {{"enabled": "1", "host": {{"{}": {{"value": "", "selected": 1}}}},
+                    "hostname": "www", "domain": "example.com", "description": ""}}}}"#,
+                parent
+            ))
+            .create_async()
+            .await;
+    }
+
+    async fn mock_del(server: &mut mockito::Server, table: &str, uuid: &str, hits: usize) -> Mock {
+        server
+            .mock(
+                "POST",
+                format!("/api/unbound/settings/del{}/{}", table, uuid).as_str(),
+            )
+            .with_body(r#"{"result": "deleted"}"#)
+            .expect(hits)
+            .create_async()
+            .await
+    }
+
+    #[rocket::async_test]
+    async fn test_delete_host_entries_matching() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = opnsense::Opnsense::new(&server.url(), None, None, true).unwrap();
+        mock_search(
+            &mut server,
+            &[
+                override_row("a-uuid", "api", "A (IPv4 address)", "10.0.0.1", true),
+                override_row("other-uuid", "api", "A (IPv4 address)", "10.0.0.2", true),
+                override_row("aaaa-uuid", "api", "AAAA (IPv6 address)", "fd00::1", true),
+                override_row("manual-uuid", "db", "A (IPv4 address)", "10.0.0.1", false),
+                override_row(
+                    "mx-uuid",
+                    "",
+                    "MX (Mail server)",
+                    "10 mail.example.com",
+                    true,
+                ),
+            ],
+            &[
+                alias_row("www-uuid", "www", "api.example.com", true),
+                alias_row("docs-uuid", "docs", "api.example.com", true),
+            ],
+        )
+        .await;
+        mock_alias_parent(&mut server, "docs-uuid", "other-uuid").await;
+        let deleted = [
+            mock_del(&mut server, "HostAlias", "www-uuid", 1).await,
+            mock_del(&mut server, "HostAlias", "docs-uuid", 0).await,
+            mock_del(&mut server, "HostOverride", "a-uuid", 1).await,
+            mock_del(&mut server, "HostOverride", "other-uuid", 0).await,
+            mock_del(&mut server, "HostOverride", "aaaa-uuid", 0).await,
+            mock_del(&mut server, "HostOverride", "manual-uuid", 0).await,
+            mock_del(&mut server, "HostOverride", "mx-uuid", 0).await,
+        ];
+
+        // Only owned rows with the same name, type and target go.
+        let queue = MutationQueue::default();
+        let mut tx = queue.begin(&opnsense).await;
+        let records = [
+            record("www.example.com", RecordType::CNAME, "api.example.com"),
+            record("api.example.com", RecordType::A, "10.0.0.1"),
+            record("db.example.com", RecordType::A, "10.0.0.1"),
+            record("example.com", RecordType::MX, "20 mail.example.com"),
+        ];
+        delete_host_entries(&mut tx, &records).await.unwrap();
+        for mock in deleted {
+            mock.assert_async().await;
+        }
+        assert_eq!(tx.len(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn test_delete_host_override_with_aliases() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = opnsense::Opnsense::new(&server.url(), None, None, true).unwrap();
+        mock_search(
+            &mut server,
+            &[
+                override_row("old-uuid", "api", "A (IPv4 address)", "10.0.0.1", true),
+                override_row("new-uuid", "api", "A (IPv4 address)", "10.0.0.2", true),
+            ],
+            &[alias_row("manual-uuid", "www", "api.example.com", false)],
+        )
+        .await;
+        mock_alias_parent(&mut server, "manual-uuid", "old-uuid").await;
+        let repoint = server
+            .mock("POST", "/api/unbound/settings/setHostAlias/manual-uuid")
+            .match_body(mockito::Matcher::JsonString(
+                r#"{"alias": {"host": "new-uuid"}}"#.to_string(),
+            ))
+            .with_body(r#"{"result": "saved"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let del_old = mock_del(&mut server, "HostOverride", "old-uuid", 1).await;
+        let del_new = mock_del(&mut server, "HostOverride", "new-uuid", 0).await;
+
+        // The alias moves to the surviving override before its parent goes.
+        let queue = MutationQueue::default();
+        let mut tx = queue.begin(&opnsense).await;
+        let records = [record("api.example.com", RecordType::A, "10.0.0.1")];
+        delete_host_entries(&mut tx, &records).await.unwrap();
+        repoint.assert_async().await;
+        del_old.assert_async().await;
+        del_new.assert_async().await;
+    }
+
+    #[rocket::async_test]
+    async fn test_delete_last_host_override_with_aliases() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = opnsense::Opnsense::new(&server.url(), None, None, true).unwrap();
+        mock_search(
+            &mut server,
+            &[override_row(
+                "api-uuid",
+                "api",
+                "A (IPv4 address)",
+                "10.0.0.1",
+                true,
+            )],
+            &[alias_row("manual-uuid", "www", "api.example.com", false)],
+        )
+        .await;
+        mock_alias_parent(&mut server, "manual-uuid", "api-uuid").await;
+        let del = mock_del(&mut server, "HostOverride", "api-uuid", 0).await;
+
+        // The last override for the name stays, as the alias has nowhere to go.
+        let queue = MutationQueue::default();
+        let mut tx = queue.begin(&opnsense).await;
+        let records = [record("api.example.com", RecordType::A, "10.0.0.1")];
+        delete_host_entries(&mut tx, &records).await.unwrap();
+        assert_eq!(tx.len(), 0);
+        del.assert_async().await;
+    }
+
+    const WEBHOOK_JSON: &str = "application/external.dns.webhook+json;version=1";
+
+    fn client_rocket(opnsense: opnsense::Opnsense) -> rocket::Rocket<rocket::Build> {
+        rocket::build()
+            .mount(
+                "/",
+                routes![
+                    healthz,
+                    status,
+                    negotiate,
+                    records_get,
+                    records_post,
+                    adjust_endpoints
+                ],
+            )
+            .manage(DomainFilter {
+                include: vec!["example.com".to_string()],
+                exclude: vec!["internal.example.com".to_string()],
+                ..Default::default()
+            })
+            .manage(opnsense)
+            .manage(TxtRegistry::default())
+            .manage(TtlPolicy::default())
+            .manage(Pipeline::new(
+                Duration::ZERO,
+                ApplyMode::default(),
+                Duration::ZERO,
+            ))
+            .attach(flush_on_shutdown())
+    }
+
+    async fn client(server: &mockito::Server) -> rocket::local::asynchronous::Client {
+        let opnsense = opnsense::Opnsense::new(&server.url(), None, None, true).unwrap();
+        rocket::local::asynchronous::Client::tracked(client_rocket(opnsense))
+            .await
+            .unwrap()
+    }
+
+    fn fixture(json: &str) -> rocket::serde::json::Value {
+        rocket::serde::json::from_str(json).unwrap()
+    }
+
+    async fn response_json(
+        response: rocket::local::asynchronous::LocalResponse<'_>,
+    ) -> rocket::serde::json::Value {
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.content_type(),
+            ContentType::parse_flexible(WEBHOOK_JSON)
+        );
+        fixture(&response.into_string().await.unwrap())
+    }
+
+    #[rocket::async_test]
+    async fn test_route_healthz() {
+        let server = mockito::Server::new_async().await;
+        let client = client(&server).await;
+        let response = client.get("/healthz").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), "OK");
+    }
+
+    #[rocket::async_test]
+    async fn test_route_negotiate() {
+        let server = mockito::Server::new_async().await;
+        let client = client(&server).await;
+        let response = client
+            .get("/")
+            .header(rocket::http::Header::new("Accept", WEBHOOK_JSON))
+            .dispatch()
+            .await;
+        assert_eq!(
+            response_json(response).await,
+            fixture(include_str!("fixtures/negotiate.response.json"))
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_route_records_get() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/api/unbound/settings/searchHostOverride/")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"searchPhrase": "_ouw_"}"#.to_string(),
+            ))
+            .with_body(include_str!(
+                "fixtures/records_get.search_host_override.json"
+            ))
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/unbound/settings/searchHostAlias/")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"searchPhrase": "_ouw_"}"#.to_string(),
+            ))
+            .with_body(include_str!("fixtures/records_get.search_host_alias.json"))
+            .create_async()
+            .await;
+
+        let client = client(&server).await;
+        let response = client.get("/records").dispatch().await;
+        assert_eq!(
+            response_json(response).await,
+            fixture(include_str!("fixtures/records_get.response.json"))
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_route_records_post() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/api/unbound/settings/searchHostOverride/")
+            .with_body(r#"{"rows": [], "rowCount": 0, "total": 0, "current": 1}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/unbound/settings/searchHostAlias/")
+            .with_body(r#"{"rows": [], "rowCount": 0, "total": 0, "current": 1}"#)
+            .create_async()
+            .await;
+        let add = server
+            .mock("POST", "/api/unbound/settings/addHostOverride/")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"host": {"hostname": "new", "domain": "example.com", "server": "192.0.2.20",
+                    "description": "_ouw_;o=default;r=service/default/new;t=120 | Created by CI"}}"#
+                    .to_string(),
+            ))
+            .with_body(r#"{"result": "saved", "uuid": "new-uuid"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let client = client(&server).await;
+        let response = client
+            .post("/records")
+            .header(ContentType::parse_flexible(WEBHOOK_JSON).unwrap())
+            .body(include_str!("fixtures/records_post.request.json"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+        add.assert_async().await;
+
+        let pipeline = client.rocket().state::<Pipeline>().unwrap();
+        assert_eq!(pipeline.applier.status().pending, 1);
+        let response = client.get("/status").dispatch().await;
+        assert_eq!(
+            fixture(&response.into_string().await.unwrap()),
+            fixture(r#"{"pendingChanges": 1, "lastApplied": null}"#)
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_route_records_post_external_cname() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/api/unbound/settings/searchHostOverride/")
+            .with_body(r#"{"rows": [], "rowCount": 0, "total": 0, "current": 1}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/unbound/settings/searchHostAlias/")
+            .with_body(r#"{"rows": [], "rowCount": 0, "total": 0, "current": 1}"#)
+            .create_async()
+            .await;
+        let add_override = server
+            .mock("POST", "/api/unbound/settings/addHostOverride/")
+            .with_body(r#"{"result": "saved", "uuid": "new-uuid"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let add_alias = server
+            .mock("POST", "/api/unbound/settings/addHostAlias/")
+            .expect(0)
+            .create_async()
+            .await;
+
+        // The CNAME to a load balancer is skipped, the rest still goes through.
+        let client = client(&server).await;
+        let response = client
+            .post("/records")
+            .header(ContentType::parse_flexible(WEBHOOK_JSON).unwrap())
+            .body(
+                r#"{"Create": [
+                    {"dnsName": "new.example.com", "targets": ["192.0.2.20"], "recordType": "A"},
+                    {"dnsName": "www.example.com", "targets": ["lb.example.net"],
+                     "recordType": "CNAME"}
+                ]}"#,
+            )
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+        add_override.assert_async().await;
+        add_alias.assert_async().await;
+    }
+
+    #[rocket::async_test]
+    async fn test_route_records_post_update() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let mut server = mockito::Server::new_async().await;
+        let added = Arc::new(AtomicBool::new(false));
+        let old = override_row("old-uuid", "api", "A (IPv4 address)", "10.0.0.1", true);
+        let new = override_row("new-uuid", "api", "A (IPv4 address)", "10.0.0.2", true);
+        server
+            .mock("POST", "/api/unbound/settings/searchHostOverride/")
+            .with_body_from_request({
+                let added = added.clone();
+                move |_| match added.load(Ordering::SeqCst) {
+                    false => search_body(std::slice::from_ref(&old)).into(),
+                    true => search_body(&[old.clone(), new.clone()]).into(),
+                }
+            })
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/unbound/settings/searchHostAlias/")
+            .with_body(search_body(&[alias_row(
+                "www-uuid",
+                "www",
+                "api.example.com",
+                true,
+            )]))
+            .create_async()
+            .await;
+        mock_alias_parent(&mut server, "www-uuid", "old-uuid").await;
+        let add = server
+            .mock("POST", "/api/unbound/settings/addHostOverride/")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"host": {"hostname": "api", "server": "10.0.0.2"}}"#.to_string(),
+            ))
+            .with_body_from_request({
+                let added = added.clone();
+                move |_| {
+                    added.store(true, Ordering::SeqCst);
+                    r#"{"result": "saved", "uuid": "new-uuid"}"#.into()
+                }
+            })
+            .expect(1)
+            .create_async()
+            .await;
+        let repoint = server
+            .mock("POST", "/api/unbound/settings/setHostAlias/www-uuid")
+            .match_body(mockito::Matcher::JsonString(
+                r#"{"alias": {"host": "new-uuid"}}"#.to_string(),
+            ))
+            .with_body(r#"{"result": "saved"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        // Deleting before the new target is in place would leave the name
+        // without an address, so that fails the batch.
+        let del = server
+            .mock("POST", "/api/unbound/settings/delHostOverride/old-uuid")
+            .with_body_from_request({
+                let added = added.clone();
+                move |_| match added.load(Ordering::SeqCst) {
+                    true => r#"{"result": "deleted"}"#.into(),
+                    false => r#"{"result": "failed"}"#.into(),
+                }
+            })
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = client(&server).await;
+        let response = client
+            .post("/records")
+            .header(ContentType::parse_flexible(WEBHOOK_JSON).unwrap())
+            .body(
+                r#"{
+                    "UpdateOld": [{"dnsName": "api.example.com", "targets": ["10.0.0.1"],
+                                   "recordType": "A", "labels": {"owner": "default"}}],
+                    "UpdateNew": [{"dnsName": "api.example.com", "targets": ["10.0.0.2"],
+                                   "recordType": "A", "labels": {"owner": "default"}}]
+                }"#,
+            )
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+        add.assert_async().await;
+        repoint.assert_async().await;
+        del.assert_async().await;
+    }
+
+    #[rocket::async_test]
+    async fn test_shutdown_applies_pending() {
+        let mut server = mockito::Server::new_async().await;
+        let reconfigure = server
+            .mock("POST", "/api/unbound/service/reconfigure")
+            .with_body(r#"{"status": "ok"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let client = client(&server).await;
+        let pipeline = client.rocket().state::<Pipeline>().unwrap();
+        pipeline.applier.mark_dirty(1);
+
+        let rocket = client.terminate().await;
+        reconfigure.assert_async().await;
+        let pipeline = rocket.state::<Pipeline>().unwrap();
+        assert_eq!(pipeline.applier.status().pending, 0);
+    }
+
+    #[rocket::async_test]
+    async fn test_route_adjust_endpoints() {
+        let server = mockito::Server::new_async().await;
+        let client = client(&server).await;
+        let response = client
+            .post("/adjustendpoints")
+            .header(ContentType::parse_flexible(WEBHOOK_JSON).unwrap())
+            .body(include_str!("fixtures/adjustendpoints.request.json"))
+            .dispatch()
+            .await;
+        assert_eq!(
+            response_json(response).await,
+            fixture(include_str!("fixtures/adjustendpoints.response.json"))
+        );
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        // Endpoints are passed through exactly as received, names included.
+        for json in [
+            include_str!("fixtures/adjustendpoints.request.json"),
+            include_str!("fixtures/records_get.response.json"),
+        ] {
+            let endpoints: Vec<models::Endpoint> = rocket::serde::json::from_str(json).unwrap();
+            let replayed = rocket::serde::json::to_string(&endpoints).unwrap();
+            assert_eq!(fixture(&replayed), fixture(json));
+        }
+
+        let endpoints: Vec<models::Endpoint> =
+            rocket::serde::json::from_str(include_str!("fixtures/adjustendpoints.request.json"))
+                .unwrap();
+        assert_eq!(endpoints[0].dns_name, "API.Example.com.");
+        assert_eq!(endpoints[2].set_identifier, "blue");
+        assert_eq!(
+            endpoints[3].record_type,
+            RecordType::Unsupported("SRV".to_string())
+        );
+    }
 }
@@ -1,94 +1,497 @@
-use opnsense::models::{HostAliasRow, HostOverrideRow};
+use crate::web::provider_specific::ProviderOptions;
+use crate::web::ttl::DEFAULT_TTL;
+use anyhow::{Context, Error, anyhow};
+use opnsense::dns_name::DnsName;
+use opnsense::models::{HostAliasRow, HostOverrideRow, HostOverrideType};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
+/// external-dns' serialized `DomainFilter`.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Filters {
-    pub filters: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    #[serde(
+        rename = "regexInclude",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub regex_include: Option<String>,
+    #[serde(
+        rename = "regexExclude",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub regex_exclude: Option<String>,
 }
 
-impl Filters {
-    pub fn new(domain: &Vec<String>) -> Self {
-        Self {
-            filters: domain.clone(),
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RecordType {
+    CNAME,
+    A,
+    AAAA,
+    MX,
+    TXT,
+    /// Any record type OPNsense cannot store, kept as sent.
+    #[serde(untagged)]
+    Unsupported(String),
+}
+
+impl TryFrom<&HostOverrideType> for RecordType {
+    type Error = Error;
+
+    fn try_from(value: &HostOverrideType) -> Result<Self, Self::Error> {
+        match value {
+            HostOverrideType::A => Ok(RecordType::A),
+            HostOverrideType::AAAA => Ok(RecordType::AAAA),
+            HostOverrideType::MX => Ok(RecordType::MX),
+            HostOverrideType::TXT => Ok(RecordType::TXT),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-pub enum RecordType {
-    CNAME,
-    A,
+/// Split an MX target such as `10 mail.example.com` into its priority and
+/// exchange. A bare exchange gets `default_priority`, if any.
+pub fn parse_mx_target(
+    target: &str,
+    default_priority: Option<u16>,
+) -> Result<(u16, String), Error> {
+    let (priority, exchange) = match (
+        target.trim().split_once(char::is_whitespace),
+        default_priority,
+    ) {
+        (Some((priority, exchange)), _) => (
+            priority
+                .parse()
+                .with_context(|| format!("Invalid MX priority in {}", target))?,
+            exchange,
+        ),
+        (None, Some(priority)) => (priority, target),
+        (None, None) => Err(anyhow!(
+            "MX target must be '<priority> <exchange>': {}",
+            target
+        ))?,
+    };
+    let exchange = DnsName::parse(exchange)?;
+    if exchange.is_empty() {
+        Err(anyhow!("MX target is missing an exchange: {}", target))?
+    }
+
+    Ok((priority, exchange.to_string()))
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ProviderSpecific {
+/// Strip the quotes external-dns wraps TXT targets in.
+pub fn unquote_txt_target(target: &str) -> String {
+    let target = target.trim();
+    target
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .unwrap_or(target)
+        .to_string()
+}
+
+/// The name served by a host override or alias, validating the hostname and
+/// domain as listed. An empty hostname is the zone apex itself.
+pub fn fqdn(hostname: &str, domain: &str) -> Result<DnsName, Error> {
+    DnsName::parse(hostname)?.join(&DnsName::parse(domain)?)
+}
+
+/// The external-dns target a host alias row represents.
+///
+/// OPNsense reports the parent host as `<hostname>.<domain>`, which starts
+/// with a dot when the parent is a zone apex.
+pub fn host_alias_target(row: &HostAliasRow) -> String {
+    row.host.trim_start_matches('.').to_string()
+}
+
+/// The external-dns target a host override row represents.
+pub fn host_override_target(row: &HostOverrideRow) -> String {
+    match row.rr {
+        HostOverrideType::MX => format!("{} {}", row.mxprio, row.mx),
+        HostOverrideType::TXT => format!("\"{}\"", row.txtdata),
+        _ => row.server.clone(),
+    }
+}
+
+/// external-dns' `ProviderSpecificProperty`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProviderSpecificProperty {
     pub name: String,
     pub value: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Endpoint {
+/// external-dns' `Endpoint`. Optional fields are omitted when empty, as
+/// external-dns does.
+///
+/// Endpoints are received with the name as sent, as an `Endpoint`, so one
+/// invalid name only drops its own endpoint. Everything past `validate` works
+/// on a `Record`, whose name is a canonical `DnsName`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record<N = DnsName> {
     #[serde(rename = "dnsName")]
-    pub dns_name: String,
+    pub dns_name: N,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
     pub targets: Vec<String>,
     #[serde(rename = "recordType")]
     pub record_type: RecordType,
-    #[serde(rename = "recordTTL")]
+    #[serde(
+        rename = "setIdentifier",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub set_identifier: String,
+    #[serde(rename = "recordTTL", default, skip_serializing_if = "is_zero")]
     pub record_ttl: i64,
-    pub labels: HashMap<String, String>,
-    #[serde(rename = "providerSpecific")]
-    pub provider_specifc: Vec<ProviderSpecific>,
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "deserialize_nullable"
+    )]
+    pub labels: BTreeMap<String, String>,
+    #[serde(
+        rename = "providerSpecific",
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "deserialize_nullable"
+    )]
+    pub provider_specific: Vec<ProviderSpecificProperty>,
 }
 
+/// An endpoint as external-dns sent it.
+pub type Endpoint = Record<String>;
+
+/// external-dns' `plan.Changes`. Empty lists arrive as `null`.
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Record {
-    #[serde(rename = "dnsName")]
-    pub dns_name: String,
-    pub targets: Vec<String>,
-    #[serde(rename = "recordType")]
-    pub record_type: RecordType,
-    #[serde(rename = "recordTTL")]
-    pub record_ttl: i64,
-    pub labels: Option<HashMap<String, String>>,
-    #[serde(rename = "providerSpecific")]
-    pub provider_specific: Option<HashMap<String, String>>,
+#[serde(bound(deserialize = "N: Deserialize<'de>"))]
+pub struct UpdateRecords<N = DnsName> {
+    #[serde(rename = "Create", default, deserialize_with = "deserialize_nullable")]
+    pub create: Vec<Record<N>>,
+    #[serde(
+        rename = "UpdateOld",
+        default,
+        deserialize_with = "deserialize_nullable"
+    )]
+    pub update_old: Vec<Record<N>>,
+    #[serde(
+        rename = "UpdateNew",
+        default,
+        deserialize_with = "deserialize_nullable"
+    )]
+    pub update_new: Vec<Record<N>>,
+    #[serde(rename = "Delete", default, deserialize_with = "deserialize_nullable")]
+    pub delete: Vec<Record<N>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct UpdateRecords {
-    #[serde(rename = "Create")]
-    pub create: Vec<Record>,
-    #[serde(rename = "UpdateOld")]
-    pub update_old: Vec<Record>,
-    #[serde(rename = "UpdateNew")]
-    pub update_new: Vec<Record>,
-    #[serde(rename = "Delete")]
-    pub delete: Vec<Record>,
-}
-
-impl From<&HostOverrideRow> for Record {
-    fn from(value: &HostOverrideRow) -> Self {
-        Record {
-            dns_name: format!("{}.{}", value.hostname, value.domain),
-            targets: vec![value.server.clone()],
-            record_type: RecordType::A,
-            record_ttl: 60,
-            labels: None,
-            provider_specific: None,
+impl UpdateRecords<String> {
+    /// Validate every endpoint, dropping those with an invalid name.
+    pub fn validate(self) -> UpdateRecords {
+        UpdateRecords {
+            create: validate(self.create),
+            update_old: validate(self.update_old),
+            update_new: validate(self.update_new),
+            delete: validate(self.delete),
         }
     }
 }
 
-impl From<&HostAliasRow> for Record {
-    fn from(value: &HostAliasRow) -> Self {
-        Record {
-            dns_name: format!("{}.{}", value.hostname, value.domain),
-            targets: vec![value.host.clone()],
+/// Validate the name of each endpoint, dropping with a warning those that are
+/// invalid.
+pub fn validate(endpoints: Vec<Endpoint>) -> Vec<Record> {
+    endpoints
+        .into_iter()
+        .filter_map(|endpoint| match Record::try_from(endpoint) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                warn!("Dropping endpoint: {:#}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+impl TryFrom<Endpoint> for Record {
+    type Error = Error;
+
+    fn try_from(value: Endpoint) -> Result<Self, Self::Error> {
+        let dns_name = DnsName::parse(&value.dns_name)
+            .with_context(|| format!("Invalid DNS name {:?}", value.dns_name))?;
+        Ok(Record {
+            dns_name,
+            targets: value.targets,
+            record_type: value.record_type,
+            set_identifier: value.set_identifier,
+            record_ttl: value.record_ttl,
+            labels: value.labels,
+            provider_specific: value.provider_specific,
+        })
+    }
+}
+
+fn is_zero(value: &i64) -> bool {
+    *value == 0
+}
+
+/// Go encodes nil slices and maps as `null`.
+fn deserialize_nullable<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+impl Record {
+    /// Normalize the record into the form `records_get` reports it in, or
+    /// explain why OPNsense cannot store it. Records without a TTL get
+    /// `default_ttl`.
+    pub fn normalize(mut self, default_ttl: i64) -> Result<Self, Error> {
+        if self.dns_name.is_empty() {
+            Err(anyhow!("Missing DNS name"))?
+        }
+        if !self.set_identifier.is_empty() {
+            Err(anyhow!(
+                "{} has a set identifier, but OPNsense has no routing policies",
+                self.dns_name
+            ))?
+        }
+        let options = ProviderOptions::parse(&self.provider_specific)
+            .with_context(|| format!("Invalid providerSpecific for {}", self.dns_name))?;
+
+        let mut targets = self
+            .targets
+            .iter()
+            .map(|target| normalize_target(&self.record_type, target, &options))
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Invalid target for {}", self.dns_name))?;
+        targets.sort();
+        targets.dedup();
+        if targets.is_empty() {
+            Err(anyhow!("{} has no targets", self.dns_name))?
+        }
+        if self.record_type == RecordType::CNAME && targets.len() > 1 {
+            Err(anyhow!("CNAME {} has more than one target", self.dns_name))?
+        }
+
+        self.targets = targets;
+        if self.record_ttl <= 0 {
+            self.record_ttl = default_ttl;
+        }
+        // Only the properties this webhook stores are reported back.
+        self.provider_specific = options.to_properties();
+        Ok(self)
+    }
+}
+
+fn normalize_target(
+    record_type: &RecordType,
+    target: &str,
+    options: &ProviderOptions,
+) -> Result<String, Error> {
+    let target = target.trim();
+    let normalized = match record_type {
+        RecordType::A => target.parse::<Ipv4Addr>()?.to_string(),
+        RecordType::AAAA => target.parse::<Ipv6Addr>()?.to_string(),
+        RecordType::CNAME => {
+            let target = DnsName::parse(target)?;
+            if target.is_empty() {
+                Err(anyhow!("Missing CNAME target"))?
+            }
+            target.to_string()
+        }
+        RecordType::MX => {
+            let (priority, exchange) = parse_mx_target(target, options.mx_priority)?;
+            format!("{} {}", priority, exchange)
+        }
+        RecordType::TXT => target.to_string(),
+        RecordType::Unsupported(record_type) => {
+            Err(anyhow!("Unsupported record type {}", record_type))?
+        }
+    };
+
+    Ok(normalized)
+}
+
+impl TryFrom<&HostOverrideRow> for Record {
+    type Error = Error;
+
+    fn try_from(value: &HostOverrideRow) -> Result<Self, Self::Error> {
+        Ok(Record {
+            dns_name: fqdn(&value.hostname, &value.domain)?,
+            targets: vec![host_override_target(value)],
+            record_type: (&value.rr).try_into()?,
+            set_identifier: "".to_string(),
+            record_ttl: DEFAULT_TTL,
+            labels: BTreeMap::new(),
+            provider_specific: vec![],
+        })
+    }
+}
+
+impl TryFrom<&HostAliasRow> for Record {
+    type Error = Error;
+
+    fn try_from(value: &HostAliasRow) -> Result<Self, Self::Error> {
+        Ok(Record {
+            dns_name: fqdn(&value.hostname, &value.domain)?,
+            targets: vec![host_alias_target(value)],
             record_type: RecordType::CNAME,
-            record_ttl: 60,
-            labels: None,
-            provider_specific: None,
+            set_identifier: "".to_string(),
+            record_ttl: DEFAULT_TTL,
+            labels: BTreeMap::new(),
+            provider_specific: vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(dns_name: &str, record_type: RecordType, targets: &[&str]) -> Record {
+        Record {
+            dns_name: dns_name.parse().unwrap(),
+            targets: targets.iter().map(|i| i.to_string()).collect(),
+            record_type,
+            set_identifier: "".to_string(),
+            record_ttl: 300,
+            labels: BTreeMap::new(),
+            provider_specific: vec![],
+        }
+    }
+
+    fn row(rr: HostOverrideType, server: &str) -> HostOverrideRow {
+        HostOverrideRow {
+            uuid: "uuid".to_string(),
+            enabled: true,
+            hostname: "api".to_string(),
+            domain: "example.com".to_string(),
+            rr,
+            mxprio: "".to_string(),
+            mx: "".to_string(),
+            server: server.to_string(),
+            txtdata: "".to_string(),
+            ttl: "".to_string(),
+            description: "".to_string(),
         }
     }
+
+    #[test]
+    fn test_host_override_address_types() {
+        let a = Record::try_from(&row(HostOverrideType::A, "10.0.0.1")).unwrap();
+        assert_eq!(a.record_type, RecordType::A);
+        assert_eq!(a.targets, vec!["10.0.0.1"]);
+
+        let aaaa = Record::try_from(&row(HostOverrideType::AAAA, "fd00::1")).unwrap();
+        assert_eq!(aaaa.dns_name, "api.example.com");
+        assert_eq!(aaaa.record_type, RecordType::AAAA);
+        assert_eq!(aaaa.targets, vec!["fd00::1"]);
+    }
+
+    #[test]
+    fn test_parse_mx_target() {
+        assert_eq!(
+            parse_mx_target("10 mail.example.com", None).unwrap(),
+            (10, "mail.example.com".to_string())
+        );
+        assert_eq!(
+            parse_mx_target("Mail.example.com.", Some(20)).unwrap(),
+            (20, "mail.example.com".to_string())
+        );
+        assert!(parse_mx_target("mail.example.com", None).is_err());
+        assert!(parse_mx_target("high mail.example.com", None).is_err());
+        assert!(parse_mx_target("10 .", None).is_err());
+    }
+
+    #[test]
+    fn test_host_override_mx() {
+        let mut mx = row(HostOverrideType::MX, "");
+        mx.hostname = "".to_string();
+        mx.mxprio = "10".to_string();
+        mx.mx = "mail.example.com".to_string();
+        let record = Record::try_from(&mx).unwrap();
+        assert_eq!(record.dns_name, "example.com");
+        assert_eq!(record.record_type, RecordType::MX);
+        assert_eq!(record.targets, vec!["10 mail.example.com"]);
+    }
+
+    #[test]
+    fn test_host_override_txt() {
+        let mut txt = row(HostOverrideType::TXT, "");
+        txt.txtdata = "v=spf1 -all".to_string();
+        let record = Record::try_from(&txt).unwrap();
+        assert_eq!(record.record_type, RecordType::TXT);
+        assert_eq!(record.targets, vec!["\"v=spf1 -all\""]);
+        assert_eq!(unquote_txt_target(&record.targets[0]), "v=spf1 -all");
+    }
+
+    #[test]
+    fn test_normalize() {
+        let normalized = record(
+            "API.Example.com.",
+            RecordType::AAAA,
+            &["fd00:0:0:0::2", "FD00::1", "fd00::2"],
+        )
+        .normalize(DEFAULT_TTL)
+        .unwrap();
+        assert_eq!(normalized.dns_name, "api.example.com");
+        assert_eq!(normalized.targets, vec!["fd00::1", "fd00::2"]);
+        assert_eq!(normalized.record_ttl, 300);
+
+        let mut mx = record("example.com", RecordType::MX, &["10  Mail.example.com."]);
+        mx.record_ttl = 0;
+        let normalized = mx.normalize(DEFAULT_TTL).unwrap();
+        assert_eq!(normalized.targets, vec!["10 mail.example.com"]);
+        assert_eq!(normalized.record_ttl, DEFAULT_TTL);
+    }
+
+    #[test]
+    fn test_normalize_rejects() {
+        assert!(
+            record(".", RecordType::A, &["127.0.0.1"])
+                .normalize(DEFAULT_TTL)
+                .is_err()
+        );
+        assert!(
+            record("a.example.com", RecordType::A, &["fd00::1"])
+                .normalize(DEFAULT_TTL)
+                .is_err()
+        );
+        assert!(
+            record("a.example.com", RecordType::MX, &["mail"])
+                .normalize(DEFAULT_TTL)
+                .is_err()
+        );
+        assert!(
+            record(
+                "a.example.com",
+                RecordType::CNAME,
+                &["b.example.com", "c.example.com"]
+            )
+            .normalize(DEFAULT_TTL)
+            .is_err()
+        );
+        assert!(
+            record(
+                "a.example.com",
+                RecordType::Unsupported("SRV".to_string()),
+                &["x"]
+            )
+            .normalize(DEFAULT_TTL)
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_unsupported_record_type() {
+        let record_type: RecordType = rocket::serde::json::from_str("\"SRV\"").unwrap();
+        assert_eq!(record_type, RecordType::Unsupported("SRV".to_string()));
+        assert_eq!(
+            rocket::serde::json::to_string(&record_type).unwrap(),
+            "\"SRV\""
+        );
+    }
 }
@@ -0,0 +1,44 @@
+use opnsense::Opnsense;
+use rocket::fairing::AdHoc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::web::{Applier, ApplyMode, MutationQueue, SnapshotCache};
+
+/// What a batch of changes passes through on its way into Unbound: the queue
+/// it waits its turn in, the cached listing it makes stale and the applier
+/// that makes it live.
+pub struct Pipeline {
+    pub queue: MutationQueue,
+    pub cache: SnapshotCache,
+    pub applier: Arc<Applier>,
+}
+
+impl Pipeline {
+    pub fn new(cache_ttl: Duration, apply_mode: ApplyMode, apply_window: Duration) -> Self {
+        Pipeline {
+            queue: MutationQueue::default(),
+            cache: SnapshotCache::new(cache_ttl),
+            applier: Arc::new(Applier::new(apply_mode, apply_window)),
+        }
+    }
+}
+
+/// A fairing applying whatever is pending when Rocket shuts down, once the
+/// batches already queued are done.
+pub fn flush_on_shutdown() -> AdHoc {
+    AdHoc::on_shutdown("Apply pending changes", |rocket| {
+        Box::pin(async move {
+            let (Some(pipeline), Some(opnsense)) =
+                (rocket.state::<Pipeline>(), rocket.state::<Opnsense>())
+            else {
+                return;
+            };
+            // Holding a turn keeps any later batch from starting meanwhile.
+            let _tx = pipeline.queue.begin(opnsense).await;
+            if let Err(e) = pipeline.applier.apply_pending(opnsense).await {
+                error!("Failed to apply changes on shutdown: {:#}", e);
+            }
+        })
+    })
+}
@@ -0,0 +1,131 @@
+use crate::web::models::ProviderSpecificProperty;
+use anyhow::{Context, Error, anyhow};
+
+/// Free text appended to the description of the rows for a record.
+pub const DESCRIPTION: &str = "webhook/opnsense-description";
+/// `true` to create the rows disabled in Unbound.
+pub const DISABLED: &str = "webhook/opnsense-disabled";
+/// Priority for MX targets given without one.
+pub const MX_PRIORITY: &str = "webhook/opnsense-mx-priority";
+/// `false` to only hang a CNAME off host overrides owned by the webhook,
+/// rather than any existing override for its target.
+pub const REUSE_PARENT: &str = "webhook/opnsense-reuse-parent";
+
+/// OPNsense-specific options for a record, set through its providerSpecific
+/// properties, i.e. `external-dns.alpha.kubernetes.io/webhook-opnsense-*`
+/// annotations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderOptions {
+    pub description: Option<String>,
+    pub disabled: bool,
+    pub mx_priority: Option<u16>,
+    pub reuse_parent: bool,
+}
+
+impl Default for ProviderOptions {
+    fn default() -> Self {
+        ProviderOptions {
+            description: None,
+            disabled: false,
+            mx_priority: None,
+            reuse_parent: true,
+        }
+    }
+}
+
+impl ProviderOptions {
+    /// Parse the known properties, ignoring any others.
+    pub fn parse(properties: &[ProviderSpecificProperty]) -> Result<Self, Error> {
+        let mut options = ProviderOptions::default();
+        for property in properties {
+            let value = property.value.trim();
+            match property.name.as_str() {
+                DESCRIPTION if !value.is_empty() => options.description = Some(value.to_string()),
+                DISABLED => options.disabled = parse_bool(DISABLED, value)?,
+                MX_PRIORITY => {
+                    let priority = value
+                        .parse()
+                        .with_context(|| format!("Invalid {}: {}", MX_PRIORITY, value))?;
+                    options.mx_priority = Some(priority);
+                }
+                REUSE_PARENT => options.reuse_parent = parse_bool(REUSE_PARENT, value)?,
+                _ => {}
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// The properties for options that differ from the defaults, sorted by
+    /// name.
+    pub fn to_properties(&self) -> Vec<ProviderSpecificProperty> {
+        let mut properties = vec![];
+        if let Some(description) = &self.description {
+            properties.push(property(DESCRIPTION, description));
+        }
+        if self.disabled {
+            properties.push(property(DISABLED, "true"));
+        }
+        if let Some(priority) = self.mx_priority {
+            properties.push(property(MX_PRIORITY, &priority.to_string()));
+        }
+        if !self.reuse_parent {
+            properties.push(property(REUSE_PARENT, "false"));
+        }
+
+        properties.sort();
+        properties
+    }
+}
+
+fn property(name: &str, value: &str) -> ProviderSpecificProperty {
+    ProviderSpecificProperty {
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, Error> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(anyhow!("Invalid {}: {}", name, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let properties = vec![
+            property(DESCRIPTION, " Managed by team dns "),
+            property(DISABLED, "True"),
+            property(MX_PRIORITY, "20"),
+            property(REUSE_PARENT, "false"),
+            property("webhook/unrelated", "x"),
+        ];
+        let options = ProviderOptions::parse(&properties).unwrap();
+        assert_eq!(
+            options,
+            ProviderOptions {
+                description: Some("Managed by team dns".to_string()),
+                disabled: true,
+                mx_priority: Some(20),
+                reuse_parent: false,
+            }
+        );
+        assert_eq!(
+            ProviderOptions::parse(&options.to_properties()).unwrap(),
+            options
+        );
+        assert!(ProviderOptions::default().to_properties().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        assert!(ProviderOptions::parse(&[property(MX_PRIORITY, "high")]).is_err());
+        assert!(ProviderOptions::parse(&[property(DISABLED, "maybe")]).is_err());
+    }
+}
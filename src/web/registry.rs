@@ -0,0 +1,187 @@
+use crate::web::metadata::Metadata;
+use crate::web::models::{Record, RecordType, unquote_txt_target};
+use opnsense::dns_name::DnsName;
+use std::collections::BTreeMap;
+
+const HERITAGE: &str = "heritage=external-dns";
+const LABEL_PREFIX: &str = "external-dns/";
+
+/// Record types external-dns may encode into registry TXT names.
+const REGISTRY_RECORD_TYPES: [RecordType; 5] = [
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::CNAME,
+    RecordType::MX,
+    RecordType::TXT,
+];
+
+/// How external-dns TXT registry records are stored.
+///
+/// OPNsense releases without TXT host overrides cannot hold the registry, so
+/// by default ownership is kept in the description of the owned rows and the
+/// registry records are synthesized from it. `prefix` and `suffix` must match
+/// the `--txt-prefix` and `--txt-suffix` given to external-dns.
+#[derive(Debug, Clone, Default)]
+pub struct TxtRegistry {
+    pub native: bool,
+    pub prefix: String,
+    pub suffix: String,
+}
+
+impl TxtRegistry {
+    /// Whether `record` is an ownership record that should be synthesized
+    /// rather than stored as a TXT host override.
+    pub fn is_registry_record(&self, record: &Record) -> bool {
+        !self.native
+            && record.record_type == RecordType::TXT
+            && record
+                .targets
+                .iter()
+                .any(|target| unquote_txt_target(target).starts_with(HERITAGE))
+    }
+
+    /// The registry TXT name external-dns uses for an endpoint.
+    pub fn txt_name(&self, dns_name: &DnsName, record_type: &RecordType) -> String {
+        let record_type = format!("{:?}", record_type).to_lowercase();
+        match dns_name.split_first() {
+            Some((first, rest)) => format!(
+                "{}{}-{}{}.{}",
+                self.prefix, record_type, first, self.suffix, rest
+            ),
+            None => format!("{}{}-{}{}", self.prefix, record_type, dns_name, self.suffix),
+        }
+    }
+
+    /// The endpoint a registry TXT name refers to. The record type is `None`
+    /// for the legacy format which does not encode it.
+    pub fn endpoint_name(&self, txt_name: &DnsName) -> Option<(DnsName, Option<RecordType>)> {
+        let name = txt_name
+            .as_str()
+            .strip_prefix(&self.prefix.to_lowercase())?;
+        let (first, rest) = match name.split_once('.') {
+            Some((first, rest)) => (first, Some(rest)),
+            None => (name, None),
+        };
+        let first = first.strip_suffix(&self.suffix.to_lowercase())?;
+
+        let mut record_type = None;
+        let mut first = first;
+        for candidate in REGISTRY_RECORD_TYPES {
+            let type_prefix = format!("{:?}-", candidate).to_lowercase();
+            if let Some(stripped) = first.strip_prefix(&type_prefix) {
+                first = stripped;
+                record_type = Some(candidate);
+                break;
+            }
+        }
+
+        let dns_name = match rest {
+            Some(rest) => format!("{}.{}", first, rest),
+            None => first.to_string(),
+        };
+        Some((dns_name.parse().ok()?, record_type))
+    }
+
+    /// Whether the registry record `registry` holds the ownership of the
+    /// endpoint `dns_name` of type `record_type`.
+    pub fn covers(&self, registry: &Record, dns_name: &DnsName, record_type: &RecordType) -> bool {
+        match self.endpoint_name(&registry.dns_name) {
+            Some((name, registry_type)) => {
+                &name == dns_name && registry_type.is_none_or(|t| &t == record_type)
+            }
+            None => false,
+        }
+    }
+
+    /// Labels carried by a registry record's target.
+    pub fn metadata(registry: &Record) -> Metadata {
+        let mut metadata = Metadata::default();
+        for target in &registry.targets {
+            let target = unquote_txt_target(target);
+            for label in target.split(',') {
+                let Some((key, value)) = label.split_once('=') else {
+                    continue;
+                };
+                if let Some(key) = key.strip_prefix(LABEL_PREFIX) {
+                    metadata.labels.insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+
+        metadata
+    }
+
+    /// The registry record external-dns expects for an owned `record`.
+    pub fn synthesize(&self, record: &Record, metadata: &Metadata) -> Option<Record> {
+        if self.native {
+            return None;
+        }
+
+        metadata.owner()?;
+        let mut target = HERITAGE.to_string();
+        for (key, value) in &metadata.labels {
+            target.push_str(&format!(",{}{}={}", LABEL_PREFIX, key, value));
+        }
+
+        Some(Record {
+            dns_name: self
+                .txt_name(&record.dns_name, &record.record_type)
+                .parse()
+                .ok()?,
+            targets: vec![format!("\"{}\"", target)],
+            record_type: RecordType::TXT,
+            set_identifier: "".to_string(),
+            record_ttl: record.record_ttl,
+            labels: BTreeMap::new(),
+            provider_specific: vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> DnsName {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn test_txt_name_round_trip() {
+        let registry = TxtRegistry::default();
+        let txt_name = registry.txt_name(&name("api.example.com"), &RecordType::AAAA);
+        assert_eq!(txt_name, "aaaa-api.example.com");
+        assert_eq!(
+            registry.endpoint_name(&name(&txt_name)),
+            Some((name("api.example.com"), Some(RecordType::AAAA)))
+        );
+    }
+
+    #[test]
+    fn test_txt_name_affixes() {
+        let registry = TxtRegistry {
+            native: false,
+            prefix: "_extdns.".to_string(),
+            suffix: "-owner".to_string(),
+        };
+        let txt_name = registry.txt_name(&name("api.example.com"), &RecordType::CNAME);
+        assert_eq!(txt_name, "_extdns.cname-api-owner.example.com");
+        assert_eq!(
+            registry.endpoint_name(&name(&txt_name)),
+            Some((name("api.example.com"), Some(RecordType::CNAME)))
+        );
+        assert_eq!(
+            registry.endpoint_name(&name("cname-api-owner.example.com")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_legacy_txt_name() {
+        let registry = TxtRegistry::default();
+        assert_eq!(
+            registry.endpoint_name(&name("api.example.com.")),
+            Some((name("api.example.com"), None))
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use crate::web::metadata;
+use anyhow::{Context, Error};
+use opnsense::Opnsense;
+use opnsense::models::{HostAlias, HostOverride};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The host overrides and aliases this webhook may own, as listed at one
+/// point in time.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub host_overrides: HostOverride,
+    pub host_aliases: HostAlias,
+}
+
+impl Snapshot {
+    /// List both tables at once.
+    pub async fn fetch(opnsense: &Opnsense) -> Result<Self, Error> {
+        let search = metadata::owned_rows();
+        let (host_overrides, host_aliases) = rocket::tokio::try_join!(
+            async {
+                opnsense
+                    .unbound_search_all_host_overrides(&search)
+                    .await
+                    .context("Failed to list host overrides")
+            },
+            async {
+                opnsense
+                    .unbound_search_all_host_aliases(&search)
+                    .await
+                    .context("Failed to list host aliases")
+            },
+        )?;
+
+        Ok(Snapshot {
+            host_overrides,
+            host_aliases,
+        })
+    }
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// Bumped on every invalidation, so a fetch that overlapped one is not
+    /// cached.
+    generation: u64,
+    snapshot: Option<(Instant, Arc<Snapshot>)>,
+}
+
+/// The snapshot `records_get` serves, reused for `ttl` so external-dns polling
+/// does not list every row from the firewall each time.
+///
+/// Batches read OPNsense directly rather than through here, and invalidate it
+/// once done, so our own changes are visible on the next read.
+pub struct SnapshotCache {
+    ttl: Duration,
+    state: Mutex<CacheState>,
+}
+
+impl SnapshotCache {
+    /// A zero `ttl` disables caching.
+    pub fn new(ttl: Duration) -> Self {
+        SnapshotCache {
+            ttl,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub async fn get(&self, opnsense: &Opnsense) -> Result<Arc<Snapshot>, Error> {
+        let generation = {
+            let state = self.state.lock().unwrap();
+            if let Some((fetched, snapshot)) = &state.snapshot
+                && fetched.elapsed() < self.ttl
+            {
+                return Ok(snapshot.clone());
+            }
+            state.generation
+        };
+
+        let snapshot = Arc::new(Snapshot::fetch(opnsense).await?);
+        let mut state = self.state.lock().unwrap();
+        if state.generation == generation && !self.ttl.is_zero() {
+            state.snapshot = Some((Instant::now(), snapshot.clone()));
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Drop the cached snapshot, and any being fetched, after OPNsense changed.
+    pub fn invalidate(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.snapshot = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY: &str = r#"{"rows": [], "rowCount": 0, "total": 0, "current": 1}"#;
+
+    #[rocket::async_test]
+    async fn test_cache_until_invalidated() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = Opnsense::new(&server.url(), None, None, true).unwrap();
+        let overrides = server
+            .mock("POST", "/api/unbound/settings/searchHostOverride/")
+            .with_body(EMPTY)
+            .expect(2)
+            .create_async()
+            .await;
+        let aliases = server
+            .mock("POST", "/api/unbound/settings/searchHostAlias/")
+            .with_body(EMPTY)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let cache = SnapshotCache::new(Duration::from_secs(60));
+        cache.get(&opnsense).await.unwrap();
+        cache.get(&opnsense).await.unwrap();
+        cache.invalidate();
+        cache.get(&opnsense).await.unwrap();
+
+        overrides.assert_async().await;
+        aliases.assert_async().await;
+    }
+
+    #[rocket::async_test]
+    async fn test_zero_ttl_disables_cache() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = Opnsense::new(&server.url(), None, None, true).unwrap();
+        let overrides = server
+            .mock("POST", "/api/unbound/settings/searchHostOverride/")
+            .with_body(EMPTY)
+            .expect(2)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/unbound/settings/searchHostAlias/")
+            .with_body(EMPTY)
+            .create_async()
+            .await;
+
+        let cache = SnapshotCache::new(Duration::ZERO);
+        cache.get(&opnsense).await.unwrap();
+        cache.get(&opnsense).await.unwrap();
+
+        overrides.assert_async().await;
+    }
+}
@@ -0,0 +1,554 @@
+use anyhow::{Error, anyhow};
+use opnsense::dns_name::DnsName;
+use opnsense::models::{
+    HostAliasRow, HostAliasUpdate, HostOverrideRow, HostOverrideUpdate, NewHostAlias,
+    NewHostOverride, Uuid,
+};
+use opnsense::{ApiError, Opnsense};
+use rocket::tokio::sync::{Mutex, MutexGuard};
+
+use crate::web::models;
+
+/// A change made to OPNsense, with what is needed to undo it.
+#[derive(Debug, Clone)]
+enum Step {
+    AddedHostOverride(Uuid),
+    AddedHostAlias(Uuid),
+    DeletedHostOverride(HostOverrideRow),
+    DeletedHostAlias(HostAliasRow),
+    /// The update that restores what was overwritten.
+    UpdatedHostOverride(Uuid, HostOverrideUpdate),
+    UpdatedHostAlias(Uuid, HostAliasUpdate),
+}
+
+/// The queue every batch of changes waits in for its turn to mutate OPNsense.
+///
+/// Rocket handles requests concurrently, and two batches planned from the same
+/// listing would race to add or delete the same rows. Batches instead run one
+/// at a time, in arrival order, each reading the rows only once it is its
+/// turn.
+#[derive(Debug, Default)]
+pub struct MutationQueue {
+    turn: Mutex<()>,
+}
+
+impl MutationQueue {
+    /// Wait for every batch queued before this one, then start a transaction.
+    /// The next batch starts once the transaction is dropped or rolled back.
+    pub async fn begin<'a>(&'a self, opnsense: &'a Opnsense) -> Transaction<'a> {
+        // Tokio's mutex is fair, so waiters are served in order.
+        let turn = self.turn.lock().await;
+        Transaction {
+            opnsense,
+            steps: vec![],
+            _turn: turn,
+        }
+    }
+}
+
+/// The changes applied for a single `UpdateRecords` batch.
+///
+/// Every mutation goes through here and is journaled, so a batch that fails
+/// halfway can be undone instead of leaving external-dns to retry on top of
+/// a partial result.
+pub struct Transaction<'a> {
+    opnsense: &'a Opnsense,
+    steps: Vec<Step>,
+    _turn: MutexGuard<'a, ()>,
+}
+
+impl<'a> Transaction<'a> {
+    /// The number of changes made so far.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// The client, for reads which need no undoing.
+    pub fn opnsense(&self) -> &'a Opnsense {
+        self.opnsense
+    }
+
+    pub async fn add_host_override(&mut self, new: &NewHostOverride) -> Result<Uuid, Error> {
+        let uuid = self.opnsense.unbound_add_host_override(new).await?;
+        self.steps.push(Step::AddedHostOverride(uuid.clone()));
+        Ok(uuid)
+    }
+
+    pub async fn add_host_alias(&mut self, new: &NewHostAlias) -> Result<Uuid, Error> {
+        let uuid = self.opnsense.unbound_add_host_alias(new).await?;
+        self.steps.push(Step::AddedHostAlias(uuid.clone()));
+        Ok(uuid)
+    }
+
+    /// Delete a listed host override. One that is gone by now, such as after
+    /// a concurrent delete by hand, counts as deleted.
+    pub async fn del_host_override(&mut self, row: &HostOverrideRow) -> Result<(), Error> {
+        let result = self
+            .opnsense
+            .unbound_del_host_override(row.uuid.clone())
+            .await;
+        if !already_gone(result, &row.uuid)? {
+            self.steps.push(Step::DeletedHostOverride(row.clone()));
+        }
+        Ok(())
+    }
+
+    /// Delete a listed host alias. One that is gone by now counts as deleted.
+    pub async fn del_host_alias(&mut self, row: &HostAliasRow) -> Result<(), Error> {
+        let result = self.opnsense.unbound_del_host_alias(row.uuid.clone()).await;
+        if !already_gone(result, &row.uuid)? {
+            self.steps.push(Step::DeletedHostAlias(row.clone()));
+        }
+        Ok(())
+    }
+
+    pub async fn set_host_override(
+        &mut self,
+        row: &HostOverrideRow,
+        update: &HostOverrideUpdate,
+    ) -> Result<(), Error> {
+        self.opnsense
+            .unbound_set_host_override(row.uuid.clone(), update)
+            .await?;
+        let previous = HostOverrideUpdate {
+            enabled: update.enabled.map(|_| row.enabled),
+            ttl: update.ttl.as_ref().map(|_| row.ttl.clone()),
+            description: update.description.as_ref().map(|_| row.description.clone()),
+        };
+        self.steps
+            .push(Step::UpdatedHostOverride(row.uuid.clone(), previous));
+        Ok(())
+    }
+
+    pub async fn set_host_alias(
+        &mut self,
+        row: &HostAliasRow,
+        update: &HostAliasUpdate,
+    ) -> Result<(), Error> {
+        self.opnsense
+            .unbound_set_host_alias(row.uuid.clone(), update)
+            .await?;
+        let previous = HostAliasUpdate {
+            enabled: update.enabled.map(|_| row.enabled),
+            host: None,
+            description: update.description.as_ref().map(|_| row.description.clone()),
+        };
+        self.steps
+            .push(Step::UpdatedHostAlias(row.uuid.clone(), previous));
+        Ok(())
+    }
+
+    /// Point a host alias at the host override `host` instead of `previous`,
+    /// which the listing does not tell.
+    pub async fn set_host_alias_parent(
+        &mut self,
+        row: &HostAliasRow,
+        previous: &Uuid,
+        host: &Uuid,
+    ) -> Result<(), Error> {
+        let update = HostAliasUpdate {
+            host: Some(host.clone()),
+            ..Default::default()
+        };
+        self.opnsense
+            .unbound_set_host_alias(row.uuid.clone(), &update)
+            .await?;
+        let previous = HostAliasUpdate {
+            host: Some(previous.clone()),
+            ..Default::default()
+        };
+        self.steps
+            .push(Step::UpdatedHostAlias(row.uuid.clone(), previous));
+        Ok(())
+    }
+
+    /// Undo every completed step, newest first.
+    ///
+    /// Rolling back is best effort: a step that cannot be undone is logged and
+    /// the rest are still attempted. Recreated rows get new UUIDs, so aliases
+    /// recreated or moved back are pointed at their recreated parent. Aliases
+    /// outside the batch are moved off an override before it is deleted, so no
+    /// other alias needs re-pointing.
+    pub async fn rollback(self) {
+        let opnsense = self.opnsense;
+        let mut recreated = vec![];
+        for step in self.steps.into_iter().rev() {
+            if let Err(e) = undo(opnsense, &step, &mut recreated).await {
+                error!("Failed to roll back {:?}: {:#}", step, e);
+            }
+        }
+    }
+}
+
+/// Whether a delete found its row already gone, in which case there is nothing
+/// to undo either.
+fn already_gone(result: Result<(), Error>, uuid: &str) -> Result<bool, Error> {
+    match result {
+        Ok(()) => Ok(false),
+        Err(e) if matches!(e.downcast_ref::<ApiError>(), Some(ApiError::NotFound(_))) => {
+            debug!("{} is already deleted", uuid);
+            Ok(true)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// A host override recreated by a rollback.
+struct Recreated {
+    old: Uuid,
+    name: DnsName,
+    new: Uuid,
+}
+
+/// Undo a single step, recording each recreated host override in `recreated`.
+async fn undo(
+    opnsense: &Opnsense,
+    step: &Step,
+    recreated: &mut Vec<Recreated>,
+) -> Result<(), Error> {
+    match step {
+        Step::AddedHostOverride(uuid) => opnsense.unbound_del_host_override(uuid.clone()).await,
+        Step::AddedHostAlias(uuid) => opnsense.unbound_del_host_alias(uuid.clone()).await,
+        Step::DeletedHostOverride(row) => {
+            let payload = NewHostOverride {
+                enabled: row.enabled,
+                hostname: row.hostname.parse()?,
+                domain: row.domain.parse()?,
+                rr: row.rr.clone(),
+                mxprio: row.mxprio.clone(),
+                mx: row.mx.clone(),
+                server: row.server.clone(),
+                txtdata: row.txtdata.clone(),
+                ttl: row.ttl.clone(),
+                description: row.description.clone(),
+            };
+            let name = models::fqdn(&row.hostname, &row.domain)?;
+            let new = opnsense.unbound_add_host_override(&payload).await?;
+            recreated.push(Recreated {
+                old: row.uuid.clone(),
+                name,
+                new,
+            });
+            Ok(())
+        }
+        Step::DeletedHostAlias(row) => {
+            // Aliases are deleted before their parent, so by now a parent
+            // deleted in the same batch has been recreated, under a new UUID.
+            let parent = models::host_alias_target(row);
+            let host = match recreated.iter().rev().find(|i| i.name == parent.as_str()) {
+                Some(i) => i.new.clone(),
+                None => opnsense
+                    .unbound_get_host_overrides()
+                    .await?
+                    .rows
+                    .iter()
+                    .find(|i| {
+                        i.enabled
+                            && models::fqdn(&i.hostname, &i.domain)
+                                .is_ok_and(|name| name == parent.as_str())
+                    })
+                    .map(|i| i.uuid.clone())
+                    .ok_or_else(|| anyhow!("Parent host override {} is gone", parent))?,
+            };
+            let payload = NewHostAlias {
+                description: row.description.clone(),
+                domain: row.domain.parse()?,
+                enabled: row.enabled,
+                hostname: row.hostname.parse()?,
+                host,
+            };
+            opnsense.unbound_add_host_alias(&payload).await?;
+            Ok(())
+        }
+        Step::UpdatedHostOverride(uuid, previous) => {
+            opnsense
+                .unbound_set_host_override(uuid.clone(), previous)
+                .await
+        }
+        Step::UpdatedHostAlias(uuid, previous) => {
+            // A previous parent deleted later in the batch is back by now,
+            // under a new UUID.
+            let mut previous = previous.clone();
+            if let Some(host) = &mut previous.host
+                && let Some(i) = recreated.iter().find(|i| &i.old == host)
+            {
+                *host = i.new.clone();
+            }
+            opnsense
+                .unbound_set_host_alias(uuid.clone(), &previous)
+                .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opnsense::models::HostOverrideType;
+
+    #[rocket::async_test]
+    async fn test_rollback_undoes_in_reverse() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = Opnsense::new(&server.url(), None, None, true).unwrap();
+
+        let add = server
+            .mock("POST", "/api/unbound/settings/addHostOverride/")
+            .with_body(r#"{"result": "saved", "uuid": "new-uuid"}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let del = server
+            .mock("POST", "/api/unbound/settings/delHostOverride/old-uuid")
+            .with_body(r#"{"result": "deleted"}"#)
+            .create_async()
+            .await;
+        let undo_add = server
+            .mock("POST", "/api/unbound/settings/delHostOverride/new-uuid")
+            .with_body(r#"{"result": "deleted"}"#)
+            .create_async()
+            .await;
+
+        let row = HostOverrideRow {
+            uuid: "old-uuid".to_string(),
+            enabled: true,
+            hostname: "api".to_string(),
+            domain: "example.com".to_string(),
+            rr: HostOverrideType::A,
+            mxprio: "".to_string(),
+            mx: "".to_string(),
+            server: "192.0.2.1".to_string(),
+            txtdata: "".to_string(),
+            ttl: "".to_string(),
+            description: "_ouw_".to_string(),
+        };
+        let new = NewHostOverride {
+            enabled: true,
+            hostname: "api".parse().unwrap(),
+            domain: "example.com".parse().unwrap(),
+            rr: HostOverrideType::A,
+            mxprio: "".to_string(),
+            mx: "".to_string(),
+            server: "192.0.2.2".to_string(),
+            txtdata: "".to_string(),
+            ttl: "".to_string(),
+            description: "_ouw_".to_string(),
+        };
+
+        let queue = MutationQueue::default();
+        let mut tx = queue.begin(&opnsense).await;
+        tx.add_host_override(&new).await.unwrap();
+        tx.del_host_override(&row).await.unwrap();
+        tx.rollback().await;
+
+        // One add for the change and one to recreate the deleted row.
+        add.assert_async().await;
+        del.assert_async().await;
+        undo_add.assert_async().await;
+    }
+
+    #[rocket::async_test]
+    async fn test_rollback_repoints_aliases() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = Opnsense::new(&server.url(), None, None, true).unwrap();
+
+        server
+            .mock("POST", "/api/unbound/settings/delHostAlias/alias-uuid")
+            .with_body(r#"{"result": "deleted"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/unbound/settings/delHostOverride/old-uuid")
+            .with_body(r#"{"result": "deleted"}"#)
+            .create_async()
+            .await;
+        let add_override = server
+            .mock("POST", "/api/unbound/settings/addHostOverride/")
+            .with_body(r#"{"result": "saved", "uuid": "new-uuid"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let add_alias = server
+            .mock("POST", "/api/unbound/settings/addHostAlias/")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"alias": {"hostname": "www", "host": "new-uuid"}}"#.to_string(),
+            ))
+            .with_body(r#"{"result": "saved", "uuid": "new-alias-uuid"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let row = HostOverrideRow {
+            uuid: "old-uuid".to_string(),
+            enabled: true,
+            hostname: "api".to_string(),
+            domain: "example.com".to_string(),
+            rr: HostOverrideType::A,
+            mxprio: "".to_string(),
+            mx: "".to_string(),
+            server: "192.0.2.1".to_string(),
+            txtdata: "".to_string(),
+            ttl: "".to_string(),
+            description: "_ouw_".to_string(),
+        };
+        let alias = HostAliasRow {
+            uuid: "alias-uuid".to_string(),
+            enabled: true,
+            host: "api.example.com".to_string(),
+            hostname: "www".to_string(),
+            domain: "example.com".to_string(),
+            description: "_ouw_".to_string(),
+        };
+
+        // The alias comes back pointing at its parent's new UUID.
+        let queue = MutationQueue::default();
+        let mut tx = queue.begin(&opnsense).await;
+        tx.del_host_alias(&alias).await.unwrap();
+        tx.del_host_override(&row).await.unwrap();
+        tx.rollback().await;
+        add_override.assert_async().await;
+        add_alias.assert_async().await;
+    }
+
+    #[rocket::async_test]
+    async fn test_rollback_moves_aliases_back() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = Opnsense::new(&server.url(), None, None, true).unwrap();
+
+        let moved = server
+            .mock("POST", "/api/unbound/settings/setHostAlias/alias-uuid")
+            .match_body(mockito::Matcher::JsonString(
+                r#"{"alias": {"host": "other-uuid"}}"#.to_string(),
+            ))
+            .with_body(r#"{"result": "saved"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/unbound/settings/delHostOverride/old-uuid")
+            .with_body(r#"{"result": "deleted"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/unbound/settings/addHostOverride/")
+            .with_body(r#"{"result": "saved", "uuid": "new-uuid"}"#)
+            .create_async()
+            .await;
+        let moved_back = server
+            .mock("POST", "/api/unbound/settings/setHostAlias/alias-uuid")
+            .match_body(mockito::Matcher::JsonString(
+                r#"{"alias": {"host": "new-uuid"}}"#.to_string(),
+            ))
+            .with_body(r#"{"result": "saved"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let row = HostOverrideRow {
+            uuid: "old-uuid".to_string(),
+            enabled: true,
+            hostname: "api".to_string(),
+            domain: "example.com".to_string(),
+            rr: HostOverrideType::A,
+            mxprio: "".to_string(),
+            mx: "".to_string(),
+            server: "192.0.2.1".to_string(),
+            txtdata: "".to_string(),
+            ttl: "".to_string(),
+            description: "_ouw_".to_string(),
+        };
+        let alias = HostAliasRow {
+            uuid: "alias-uuid".to_string(),
+            enabled: true,
+            host: "api.example.com".to_string(),
+            hostname: "www".to_string(),
+            domain: "example.com".to_string(),
+            description: "Added by hand".to_string(),
+        };
+
+        // The alias goes back to its old parent, under the parent's new UUID.
+        let queue = MutationQueue::default();
+        let mut tx = queue.begin(&opnsense).await;
+        tx.set_host_alias_parent(&alias, &row.uuid, &"other-uuid".to_string())
+            .await
+            .unwrap();
+        tx.del_host_override(&row).await.unwrap();
+        tx.rollback().await;
+        moved.assert_async().await;
+        moved_back.assert_async().await;
+    }
+
+    #[rocket::async_test]
+    async fn test_del_already_deleted() {
+        let mut server = mockito::Server::new_async().await;
+        let opnsense = Opnsense::new(&server.url(), None, None, true).unwrap();
+
+        server
+            .mock("POST", "/api/unbound/settings/delHostOverride/gone-uuid")
+            .with_body(r#"{"result": "not found"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/unbound/settings/delHostAlias/gone-uuid")
+            .with_body(r#"{"result": "not found"}"#)
+            .create_async()
+            .await;
+        let add = server
+            .mock(
+                "POST",
+                mockito::Matcher::Regex("^/api/unbound/settings/add".to_string()),
+            )
+            .expect(0)
+            .create_async()
+            .await;
+
+        let row = HostOverrideRow {
+            uuid: "gone-uuid".to_string(),
+            enabled: true,
+            hostname: "api".to_string(),
+            domain: "example.com".to_string(),
+            rr: HostOverrideType::A,
+            mxprio: "".to_string(),
+            mx: "".to_string(),
+            server: "192.0.2.1".to_string(),
+            txtdata: "".to_string(),
+            ttl: "".to_string(),
+            description: "_ouw_".to_string(),
+        };
+        let alias = HostAliasRow {
+            uuid: "gone-uuid".to_string(),
+            enabled: true,
+            host: "api.example.com".to_string(),
+            hostname: "www".to_string(),
+            domain: "example.com".to_string(),
+            description: "_ouw_".to_string(),
+        };
+
+        // Rows deleted in the meantime count as deleted and are not recreated.
+        let queue = MutationQueue::default();
+        let mut tx = queue.begin(&opnsense).await;
+        tx.del_host_alias(&alias).await.unwrap();
+        tx.del_host_override(&row).await.unwrap();
+        assert_eq!(tx.len(), 0);
+        tx.rollback().await;
+        add.assert_async().await;
+    }
+
+    #[rocket::async_test]
+    async fn test_queue_serializes_batches() {
+        let opnsense = Opnsense::new("http://127.0.0.1:1", None, None, true).unwrap();
+        let queue = MutationQueue::default();
+
+        let first = queue.begin(&opnsense).await;
+        let waiting = rocket::tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            queue.begin(&opnsense),
+        );
+        assert!(waiting.await.is_err());
+
+        first.rollback().await;
+        let second = rocket::tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            queue.begin(&opnsense),
+        );
+        assert!(second.await.is_ok());
+    }
+}
@@ -0,0 +1,41 @@
+/// The TTL reported for records without one, unless configured otherwise.
+pub const DEFAULT_TTL: i64 = 60;
+
+/// How record TTLs are stored and reported.
+///
+/// The TTL external-dns asks for is kept in the row description, so it is
+/// reported back unchanged. Rows without one, such as those created by hand,
+/// report `default`.
+#[derive(Debug, Clone)]
+pub struct TtlPolicy {
+    pub default: i64,
+    /// Also set the TTL on host overrides. Requires an OPNsense release with
+    /// per-override TTLs.
+    pub host_overrides: bool,
+}
+
+impl Default for TtlPolicy {
+    fn default() -> Self {
+        TtlPolicy {
+            default: DEFAULT_TTL,
+            host_overrides: false,
+        }
+    }
+}
+
+impl TtlPolicy {
+    /// The TTL to store for a record requested with `ttl`. external-dns sends
+    /// zero when none is configured.
+    pub fn resolve(&self, ttl: i64) -> i64 {
+        if ttl > 0 { ttl } else { self.default }
+    }
+
+    /// The `ttl` field of a host override for a stored TTL, empty for Unbound's
+    /// default.
+    pub fn host_override_ttl(&self, ttl: Option<i64>) -> String {
+        match ttl {
+            Some(ttl) if self.host_overrides => ttl.to_string(),
+            _ => "".to_string(),
+        }
+    }
+}